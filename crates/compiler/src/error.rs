@@ -1,27 +1,272 @@
-use crate::prelude::Str;
+use crate::{
+    lexer::{Lexer, Token, TokenType},
+    prelude::Str,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Error {
-    UnexpectedEof,
-    UnexpectedToken(Str),
-    UnexpectedExpression,
+    /// The source ended while `expected` could follow, `at` is the empty slice at its end
+    UnexpectedEof {
+        at: Str,
+        expected: Vec<Expected>,
+        context: Vec<Context>,
+    },
+    /// `found` cannot appear here, `expected` is empty if no single token would fit
+    UnexpectedToken {
+        found: Token,
+        expected: Vec<Expected>,
+        context: Vec<Context>,
+    },
+    UnexpectedExpression(Str),
     InvalidEscapeSequence(Str),
     InvalidFloat(Str),
     InvalidToken(Str),
-    InvalidUnaryExpression(Str),
+    MissingTypeOrInitializer(Str),
+    MissingInitializer(Str),
+    InitializationCycle(Str),
+    InvalidCfg(Str),
+    NonAssociativeOperator(Str),
+    InvalidAttribute(Str),
+    /// `break 'name` or `continue 'name` without an enclosing loop labeled `'name`
+    UnknownLabel(Str),
+    /// `return`, `?`, `break` or `continue` leaving a deferred statement
+    JumpOutOfDefer(Str),
+    /// A parameter named `self` that is not the receiver, e.g. `Self self` or `int a, *self`
+    InvalidReceiver(Str),
+    /// An associated type of an impl without `= type`
+    MissingTypeTarget(Str),
+    /// `break` or `continue` outside of any loop
+    JumpOutsideLoop(Str),
+    /// The value of a `break` out of a `while` or `for` loop
+    InvalidBreakValue(Str),
+    /// Attributes in front of the receiver, e.g. `@cfg(test) self`
+    AttributeOnReceiver(Str),
+    /// `pub` in front of an item that cannot have a visibility, e.g. `pub impl` or `pub extern {`
+    VisibilityNotAllowed(Str),
+}
+
+/// Something the parser looked for where an error occurred
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expected {
+    Token(TokenType),
+    /// Any binary operator
+    Operator,
+    /// Any expression, including its unary operators
+    Expression,
+    /// A word that is only a keyword in some places and lexed as an identifier, e.g. `package`
+    Contextual(&'static str),
+}
+
+impl Expected {
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Token(token_type) => token_type.describe(),
+            Self::Operator => "operator".into(),
+            Self::Expression => "expression".into(),
+            Self::Contextual(word) => format!("`{word}`"),
+        }
+    }
+}
+
+/// Something the parser was inside of when an error occurred
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    pub kind: ContextKind,
+    pub name: Option<Str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContextKind {
+    Module,
+    Import,
+    Constant,
+    Global,
+    Trait,
+    Struct,
+    Enum,
+    TypeAlias,
+    Impl,
+    Extern,
+    Function,
+    AssociatedType,
+    StructField,
+    EnumVariant,
+    Parameter,
+    Attribute,
+}
+
+impl ContextKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Module => "module",
+            Self::Import => "import",
+            Self::Constant => "constant",
+            Self::Global => "global",
+            Self::Trait => "trait",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::TypeAlias => "type alias",
+            Self::Impl => "impl",
+            Self::Extern => "extern block",
+            Self::Function => "function",
+            Self::AssociatedType => "associated type",
+            Self::StructField => "struct field",
+            Self::EnumVariant => "enum variant",
+            Self::Parameter => "parameter",
+            Self::Attribute => "attribute",
+        }
+    }
 }
 
 impl Error {
     pub fn slice(&self) -> Option<Str> {
         match self {
-            Error::UnexpectedEof | Error::UnexpectedExpression => None,
-            Error::UnexpectedToken(slice)
+            Error::UnexpectedEof { at: slice, .. }
+            | Error::UnexpectedToken {
+                found: Token { slice, .. },
+                ..
+            }
+            | Error::UnexpectedExpression(slice)
             | Error::InvalidEscapeSequence(slice)
             | Error::InvalidFloat(slice)
             | Error::InvalidToken(slice)
-            | Error::InvalidUnaryExpression(slice) => Some(*slice),
+            | Error::MissingTypeOrInitializer(slice)
+            | Error::MissingInitializer(slice)
+            | Error::InitializationCycle(slice)
+            | Error::InvalidCfg(slice)
+            | Error::NonAssociativeOperator(slice)
+            | Error::InvalidAttribute(slice)
+            | Error::UnknownLabel(slice)
+            | Error::JumpOutOfDefer(slice)
+            | Error::InvalidReceiver(slice)
+            | Error::MissingTypeTarget(slice)
+            | Error::JumpOutsideLoop(slice)
+            | Error::InvalidBreakValue(slice)
+            | Error::AttributeOnReceiver(slice)
+            | Error::VisibilityNotAllowed(slice) => Some(*slice),
+        }
+    }
+
+    /// Identifies the kind of error, codes are numbered in declaration order.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnexpectedEof { .. } => "E0001",
+            Error::UnexpectedToken { .. } => "E0002",
+            Error::UnexpectedExpression(_) => "E0003",
+            Error::InvalidEscapeSequence(_) => "E0004",
+            Error::InvalidFloat(_) => "E0005",
+            Error::InvalidToken(_) => "E0006",
+            Error::MissingTypeOrInitializer(_) => "E0007",
+            Error::MissingInitializer(_) => "E0008",
+            Error::InitializationCycle(_) => "E0009",
+            Error::InvalidCfg(_) => "E0010",
+            Error::NonAssociativeOperator(_) => "E0011",
+            Error::InvalidAttribute(_) => "E0012",
+            Error::UnknownLabel(_) => "E0013",
+            Error::JumpOutOfDefer(_) => "E0014",
+            Error::InvalidReceiver(_) => "E0015",
+            Error::MissingTypeTarget(_) => "E0016",
+            Error::JumpOutsideLoop(_) => "E0017",
+            Error::InvalidBreakValue(_) => "E0018",
+            Error::AttributeOnReceiver(_) => "E0019",
+            Error::VisibilityNotAllowed(_) => "E0020",
+        }
+    }
+
+    /// Adds the enclosing `context` to a parse error, contexts are ordered innermost first.
+    pub fn in_context(mut self, context: Context) -> Self {
+        if let Error::UnexpectedEof {
+            context: contexts, ..
+        }
+        | Error::UnexpectedToken {
+            context: contexts, ..
+        } = &mut self
+        {
+            contexts.push(context);
+        }
+        self
+    }
+
+    /// Describes the error in a single line, e.g. "expected `;`, found `}` in struct field of
+    /// struct `Vec3`".
+    pub fn message(&self, lex: &Lexer) -> String {
+        let (found, expected, context) = match self {
+            Error::UnexpectedEof {
+                expected, context, ..
+            } => ("end of file".to_string(), expected, context),
+            Error::UnexpectedToken {
+                found,
+                expected,
+                context,
+            } => {
+                let found = match found.r#type {
+                    TokenType::Integer
+                    | TokenType::Float
+                    | TokenType::String
+                    | TokenType::Identifier => {
+                        format!("{} `{}`", found.r#type.describe(), lex.slice(found.slice))
+                    }
+                    _ => found.r#type.describe(),
+                };
+                (found, expected, context)
+            }
+            Error::UnexpectedExpression(_) => return "expression cannot be assigned to".into(),
+            Error::InvalidEscapeSequence(_) => return "invalid escape sequence".into(),
+            Error::InvalidFloat(_) => return "invalid float literal".into(),
+            Error::InvalidToken(_) => return "invalid token".into(),
+            Error::MissingTypeOrInitializer(_) => {
+                return "global needs a type or an initializer".into()
+            }
+            Error::MissingInitializer(_) => return "missing initializer".into(),
+            Error::InitializationCycle(_) => return "globals initialize each other".into(),
+            Error::InvalidCfg(_) => return "invalid `@cfg` predicate".into(),
+            Error::NonAssociativeOperator(_) => {
+                return "comparison operators cannot be chained".into()
+            }
+            Error::InvalidAttribute(_) => return "invalid attribute arguments".into(),
+            Error::UnknownLabel(_) => return "no enclosing loop has this label".into(),
+            Error::JumpOutOfDefer(_) => return "cannot jump out of a deferred statement".into(),
+            Error::InvalidReceiver(_) => {
+                return "`self` must be the first parameter, as `self`, `*self` or `*const self`"
+                    .into()
+            }
+            Error::MissingTypeTarget(_) => return "associated type needs a target type".into(),
+            Error::JumpOutsideLoop(_) => {
+                return "`break` and `continue` need an enclosing loop".into()
+            }
+            Error::InvalidBreakValue(_) => {
+                return "only `loop` can be left with a value, not `while` or `for`".into()
+            }
+            Error::AttributeOnReceiver(_) => return "attributes are not allowed on `self`".into(),
+            Error::VisibilityNotAllowed(_) => {
+                return "impls and extern blocks cannot be marked as `pub`, only their members"
+                    .into()
+            }
+        };
+        let mut message = match expected.as_slice() {
+            [] => format!("unexpected {found}"),
+            [expected] => format!("expected {}, found {found}", expected.describe()),
+            [expected @ .., last] => {
+                let expected = expected
+                    .iter()
+                    .map(|expected| expected.describe())
+                    .collect::<Vec<_>>();
+                format!(
+                    "expected one of {} or {}, found {found}",
+                    expected.join(", "),
+                    last.describe()
+                )
+            }
+        };
+        for (index, context) in context.iter().enumerate() {
+            message.push_str(if index == 0 { " in " } else { " of " });
+            message.push_str(context.kind.describe());
+            if let Some(name) = context.name {
+                message.push_str(&format!(" `{}`", lex.slice(name)));
+            }
         }
+        message
     }
 }
@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+
+use crate::{
+    lexer::{Lexer, TokenType},
+    prelude::*,
+};
+
+use super::{
+    Hir, HirAttribute, HirAttributeArg, HirBlock, HirConst, HirEnumVariant, HirExpression,
+    HirExpressionKind, HirFunction, HirModuleBody, HirStatementKind, HirTypeDecl,
+};
+
+/// The configuration `@cfg(...)` attributes are evaluated against
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Enables `@cfg(test)`
+    pub test: bool,
+    /// Enables `@cfg(debug)`
+    pub debug: bool,
+    /// Enables `@cfg(feature = "name")` for every contained name
+    pub features: HashSet<String>,
+}
+
+/// Removes every item, member, parameter and statement of `hir` that is disabled by a `@cfg(...)`
+/// attribute under `config`.
+///
+/// `@cfg(...)` takes `test`, `debug`, `!test`, `!debug` and `feature = "name"`. A node is enabled
+/// if all arguments of all of its `@cfg(...)` attributes hold.
+///
+/// Fails with [`Error::InvalidCfg`] if an argument is not one of these.
+pub fn strip(hir: &mut Hir, lex: &Lexer, config: &Config) -> Result<()> {
+    Stripper { lex, config }.module_body(&mut hir.root)
+}
+
+struct Stripper<'l> {
+    lex: &'l Lexer,
+    config: &'l Config,
+}
+
+impl Stripper<'_> {
+    fn enabled(&self, attributes: &[HirAttribute]) -> Result<bool> {
+        for attribute in attributes {
+            if self.lex.slice(attribute.name) != "cfg" {
+                continue;
+            }
+            if attribute.args.is_empty() {
+                return Err(Error::InvalidCfg(attribute.name));
+            }
+            for arg in &attribute.args {
+                if !self
+                    .predicate(arg)
+                    .ok_or(Error::InvalidCfg(attribute.name))?
+                {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Evaluates a single argument, `None` if it is invalid.
+    fn predicate(&self, arg: &HirAttributeArg) -> Option<bool> {
+        if let Some(name) = arg.name {
+            let HirExpressionKind::String { slice } = arg.value.kind else {
+                return None;
+            };
+            if self.lex.slice(name) != "feature" {
+                return None;
+            }
+            let feature = self.lex.unescape(Lexer::string_contents(slice));
+            return Some(self.config.features.contains(feature.as_ref()));
+        }
+        self.flag(&arg.value)
+    }
+
+    fn flag(&self, expr: &HirExpression) -> Option<bool> {
+        match &expr.kind {
+            HirExpressionKind::Access { name } => match self.lex.slice(*name) {
+                "test" => Some(self.config.test),
+                "debug" => Some(self.config.debug),
+                _ => None,
+            },
+            HirExpressionKind::UnaryOp { op, arg } if op.r#type == TokenType::Bang => {
+                self.flag(arg).map(|enabled| !enabled)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the disabled elements of `items`.
+    fn retain<T>(
+        &self,
+        items: &mut Vec<T>,
+        attributes: impl Fn(&T) -> &[HirAttribute],
+    ) -> Result<()> {
+        let enabled = items
+            .iter()
+            .map(|item| self.enabled(attributes(item)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut enabled = enabled.into_iter();
+        items.retain(|_| enabled.next().unwrap());
+        Ok(())
+    }
+
+    fn module_body(&self, body: &mut HirModuleBody) -> Result<()> {
+        self.retain(&mut body.modules, |module| &module.attributes)?;
+        for module in &mut body.modules {
+            if let Some(body) = &mut module.body {
+                self.module_body(body)?;
+            }
+        }
+        self.retain(&mut body.imports, |import| &import.attributes)?;
+        self.constants(&mut body.constants)?;
+        self.retain(&mut body.globals, |global| &global.attributes)?;
+        for global in &mut body.globals {
+            if let Some(expr) = &mut global.expr {
+                self.expr(expr)?;
+            }
+        }
+        self.retain(&mut body.types, |decl| match decl {
+            HirTypeDecl::Trait { attributes, .. }
+            | HirTypeDecl::Struct { attributes, .. }
+            | HirTypeDecl::Enum { attributes, .. }
+            | HirTypeDecl::Alias { attributes, .. } => attributes,
+        })?;
+        for decl in &mut body.types {
+            match decl {
+                HirTypeDecl::Trait {
+                    types,
+                    constants,
+                    functions,
+                    ..
+                } => {
+                    self.retain(types, |r#type| &r#type.attributes)?;
+                    self.constants(constants)?;
+                    self.functions(functions)?;
+                }
+                HirTypeDecl::Struct {
+                    fields,
+                    constants,
+                    functions,
+                    ..
+                } => {
+                    self.retain(fields, |field| &field.attributes)?;
+                    self.constants(constants)?;
+                    self.functions(functions)?;
+                }
+                HirTypeDecl::Enum {
+                    variants,
+                    constants,
+                    functions,
+                    ..
+                } => {
+                    self.retain(variants, |variant| match variant {
+                        HirEnumVariant::Empty { attributes, .. }
+                        | HirEnumVariant::Tuple { attributes, .. }
+                        | HirEnumVariant::Struct { attributes, .. } => attributes,
+                    })?;
+                    for variant in variants {
+                        if let HirEnumVariant::Struct { fields, .. } = variant {
+                            self.retain(fields, |field| &field.attributes)?;
+                        }
+                    }
+                    self.constants(constants)?;
+                    self.functions(functions)?;
+                }
+                HirTypeDecl::Alias { .. } => {}
+            }
+        }
+        self.retain(&mut body.impls, |imp| &imp.attributes)?;
+        for imp in &mut body.impls {
+            self.retain(&mut imp.types, |r#type| &r#type.attributes)?;
+            self.constants(&mut imp.constants)?;
+            self.functions(&mut imp.functions)?;
+        }
+        self.functions(&mut body.functions)
+    }
+
+    fn constants(&self, constants: &mut Vec<HirConst>) -> Result<()> {
+        self.retain(constants, |constant| &constant.attributes)?;
+        for constant in constants {
+            if let Some(expr) = &mut constant.expr {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn functions(&self, functions: &mut Vec<HirFunction>) -> Result<()> {
+        self.retain(functions, |function| &function.attributes)?;
+        for function in functions {
+            self.retain(&mut function.params, |param| &param.attributes)?;
+            if let Some(body) = &mut function.body {
+                self.block(body)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&self, block: &mut HirBlock) -> Result<()> {
+        self.retain(&mut block.statements, |statement| &statement.attributes)?;
+        for statement in &mut block.statements {
+            self.statement(&mut statement.kind)?;
+        }
+        if let Some(tail) = &mut block.tail {
+            self.expr(tail)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&self, statement: &mut HirStatementKind) -> Result<()> {
+        match statement {
+            HirStatementKind::VarDecl { expr, .. }
+            | HirStatementKind::ConstDecl { expr, .. }
+            | HirStatementKind::Return { expr }
+            | HirStatementKind::Break { value: expr, .. } => {
+                if let Some(expr) = expr {
+                    self.expr(expr)?;
+                }
+            }
+            HirStatementKind::Assign { expr, value } => {
+                self.expr(expr)?;
+                self.expr(value)?;
+            }
+            HirStatementKind::If {
+                cond,
+                block,
+                else_block,
+            } => {
+                self.expr(cond)?;
+                self.block(block)?;
+                if let Some(else_block) = else_block {
+                    self.block(else_block)?;
+                }
+            }
+            HirStatementKind::While { cond, block, .. } => {
+                self.expr(cond)?;
+                self.block(block)?;
+            }
+            HirStatementKind::For { expr, block, .. } => {
+                self.expr(expr)?;
+                self.block(block)?;
+            }
+            HirStatementKind::Loop { block, .. } => self.block(block)?,
+            HirStatementKind::Call { expr, args } => {
+                self.expr(expr)?;
+                for arg in args {
+                    self.expr(arg)?;
+                }
+            }
+            HirStatementKind::Expr { expr } => self.expr(expr)?,
+            HirStatementKind::Defer { statement } => self.statement(&mut statement.kind)?,
+            HirStatementKind::Continue { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Strips the blocks nested in `expr`.
+    fn expr(&self, expr: &mut HirExpression) -> Result<()> {
+        match &mut expr.kind {
+            HirExpressionKind::Int { .. }
+            | HirExpressionKind::Float { .. }
+            | HirExpressionKind::String { .. }
+            | HirExpressionKind::Access { .. } => {}
+            HirExpressionKind::DotAccess { expr, .. }
+            | HirExpressionKind::SafeDotAccess { expr, .. }
+            | HirExpressionKind::AddressOf { expr, .. }
+            | HirExpressionKind::Deref { expr }
+            | HirExpressionKind::Try { expr }
+            | HirExpressionKind::Cast { expr, .. }
+            | HirExpressionKind::UnaryOp { arg: expr, .. } => self.expr(expr)?,
+            HirExpressionKind::IndexAccess { expr, index } => {
+                self.expr(expr)?;
+                self.expr(index)?;
+            }
+            HirExpressionKind::Call { expr, args } => {
+                self.expr(expr)?;
+                for arg in args {
+                    self.expr(arg)?;
+                }
+            }
+            HirExpressionKind::BinaryOp { args, .. } | HirExpressionKind::Coalesce { args } => {
+                for arg in args.iter_mut() {
+                    self.expr(arg)?;
+                }
+            }
+            HirExpressionKind::Block { block } | HirExpressionKind::Loop { block, .. } => {
+                self.block(block)?
+            }
+            HirExpressionKind::If {
+                cond,
+                block,
+                else_block,
+            } => {
+                self.expr(cond)?;
+                self.block(block)?;
+                if let Some(else_block) = else_block {
+                    self.block(else_block)?;
+                }
+            }
+            HirExpressionKind::Match { expr, arms } => {
+                self.expr(expr)?;
+                for arm in arms {
+                    self.expr(&mut arm.body)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::hir::parser::Parser;
+    use crate::prelude::*;
+
+    use super::{strip, Config, HirExpressionKind, HirStatementKind, HirTypeDecl};
+
+    const SOURCE: &str = r#"
+@cfg(test)
+module tests {
+    fun helper() {}
+}
+
+@cfg(feature = "simd")
+fun fast() {}
+
+@cfg(!test, debug)
+fun debug_only() {}
+
+struct Vec3 {
+    float x;
+    @cfg(feature = "four")
+    float w;
+}
+
+fun main() {
+    @cfg(debug)
+    log("debug");
+    if ready {
+        #[cfg(!debug)]
+        log("release");
+    }
+    run();
+}
+"#;
+
+    fn parse_stripped(config: &Config) -> Result<Parser> {
+        let mut parser = Parser::new(Rc::from(SOURCE));
+        parser.parse()?;
+        strip(&mut parser.ast, &parser.lex, config)?;
+        Ok(parser)
+    }
+
+    fn function_names(parser: &Parser) -> Vec<&str> {
+        parser
+            .ast
+            .root
+            .functions
+            .iter()
+            .map(|function| parser.lex.slice(function.name))
+            .collect()
+    }
+
+    fn statement_count(parser: &Parser) -> (usize, usize) {
+        let main = parser.ast.root.functions.last().unwrap();
+        let body = main.body.as_ref().unwrap();
+        let HirStatementKind::If { block, .. } = &body.statements[body.statements.len() - 2].kind
+        else {
+            panic!("expected an if statement");
+        };
+        (body.statements.len(), block.statements.len())
+    }
+
+    #[test]
+    fn default_config() {
+        let parser = parse_stripped(&Config::default()).unwrap();
+        assert!(parser.ast.root.modules.is_empty());
+        assert_eq!(function_names(&parser), ["main"]);
+        let HirTypeDecl::Struct { fields, .. } = &parser.ast.root.types[0] else {
+            panic!("expected a struct");
+        };
+        assert_eq!(fields.len(), 1);
+        assert_eq!(statement_count(&parser), (2, 1));
+    }
+
+    #[test]
+    fn enabled_config() {
+        let config = Config {
+            test: true,
+            debug: true,
+            features: ["simd".to_string()].into(),
+        };
+        let parser = parse_stripped(&config).unwrap();
+        assert_eq!(parser.ast.root.modules.len(), 1);
+        assert_eq!(function_names(&parser), ["fast", "main"]);
+        assert_eq!(statement_count(&parser), (3, 0));
+    }
+
+    #[test]
+    fn debug_without_test() {
+        let config = Config {
+            debug: true,
+            ..Config::default()
+        };
+        let parser = parse_stripped(&config).unwrap();
+        assert_eq!(function_names(&parser), ["debug_only", "main"]);
+    }
+
+    #[test]
+    fn member_constants_and_escaped_features() {
+        let source = r#"
+impl S {
+    const A = {
+        @cfg(debug)
+        log("debug");
+        1
+    };
+}
+@cfg(feature = "a\x2db\"")
+fun dashed() {}
+"#;
+        let mut parser = Parser::new(Rc::from(source));
+        parser.parse().unwrap();
+        let config = Config {
+            features: ["a-b\"".to_string()].into(),
+            ..Config::default()
+        };
+        strip(&mut parser.ast, &parser.lex, &config).unwrap();
+        let expr = parser.ast.root.impls[0].constants[0].expr.as_ref().unwrap();
+        let HirExpressionKind::Block { block } = &expr.kind else {
+            panic!("expected a block");
+        };
+        assert!(block.statements.is_empty());
+        assert_eq!(function_names(&parser), ["dashed"]);
+    }
+
+    #[test]
+    fn invalid_predicate() {
+        let mut parser = Parser::new(Rc::from("@cfg(release)\nfun main() {}"));
+        parser.parse().unwrap();
+        let result = strip(&mut parser.ast, &parser.lex, &Config::default());
+        assert!(matches!(result, Err(Error::InvalidCfg(_))));
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{lexer::Lexer, prelude::*};
+
+use super::{
+    Hir, HirBlock, HirExpression, HirExpressionKind, HirGlobal, HirImport, HirModuleBody,
+    HirPattern, HirPatternKind, HirStatementKind,
+};
+
+/// A module-level variable and the path of the module it is defined in
+#[derive(Debug)]
+pub struct InitGlobal<'a> {
+    pub module: Vec<Str>,
+    pub global: &'a HirGlobal,
+}
+
+/// Returns the globals of `hir` in the order in which they are initialized.
+///
+/// Globals are initialized in declaration order, the globals of a module come before the globals
+/// of its submodules. A global whose initializer references other globals is initialized after
+/// them. References are resolved relative to the defining module, through its imports and from
+/// the root module, the first of these scopes that defines the name is used. Functions called by an
+/// initializer are not inspected.
+///
+/// Fails with [`Error::InitializationCycle`] if initializers reference each other.
+pub fn initialization_order<'a>(hir: &'a Hir, lex: &Lexer) -> Result<Vec<InitGlobal<'a>>> {
+    let mut graph = Graph {
+        lex,
+        nodes: Vec::with_capacity(0),
+        by_path: HashMap::new(),
+        names: HashSet::new(),
+    };
+    graph.collect(&hir.root, &mut Vec::with_capacity(0));
+    let deps = (0..graph.nodes.len())
+        .map(|index| graph.dependencies(index))
+        .collect::<Vec<_>>();
+    let mut marks = vec![Mark::Unvisited; graph.nodes.len()];
+    let mut order = Vec::with_capacity(graph.nodes.len());
+    for index in 0..graph.nodes.len() {
+        graph.visit(index, &deps, &mut marks, &mut order)?;
+    }
+    let mut nodes = graph.nodes.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order
+        .into_iter()
+        .map(|index| {
+            let node = nodes[index].take().unwrap();
+            InitGlobal {
+                module: node.module,
+                global: node.global,
+            }
+        })
+        .collect())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+struct Node<'a> {
+    module: Vec<Str>,
+    imports: &'a [HirImport],
+    global: &'a HirGlobal,
+}
+
+struct Graph<'a, 'l> {
+    lex: &'l Lexer,
+    nodes: Vec<Node<'a>>,
+    /// Dot separated path of every global
+    by_path: HashMap<String, usize>,
+    /// Dot separated path of every module-level name, e.g. constants, functions and submodules
+    names: HashSet<String>,
+}
+
+impl<'a, 'l> Graph<'a, 'l> {
+    fn collect(&mut self, body: &'a HirModuleBody, module: &mut Vec<Str>) {
+        let names = (body.modules.iter().map(|submodule| submodule.name))
+            .chain(body.constants.iter().map(|constant| constant.name))
+            .chain(body.globals.iter().map(|global| global.name))
+            .chain(body.types.iter().map(|decl| decl.name()))
+            .chain(body.functions.iter().map(|function| function.name));
+        for name in names {
+            let mut path = self.strs(module);
+            path.push(self.lex.slice(name));
+            self.names.insert(path.join("."));
+        }
+        for global in &body.globals {
+            let mut path = self.strs(module);
+            path.push(self.lex.slice(global.name));
+            self.by_path.insert(path.join("."), self.nodes.len());
+            self.nodes.push(Node {
+                module: module.clone(),
+                imports: &body.imports,
+                global,
+            });
+        }
+        for submodule in &body.modules {
+            let Some(body) = &submodule.body else {
+                continue;
+            };
+            module.push(submodule.name);
+            self.collect(body, module);
+            module.pop();
+        }
+    }
+
+    fn dependencies(&self, index: usize) -> Vec<usize> {
+        let node = &self.nodes[index];
+        let Some(expr) = &node.global.expr else {
+            return Vec::with_capacity(0);
+        };
+        let mut refs = References {
+            lex: self.lex,
+            locals: Vec::with_capacity(0),
+            paths: Vec::with_capacity(0),
+        };
+        refs.expr(expr);
+        refs.paths
+            .iter()
+            .filter_map(|path| self.resolve(node, path))
+            .collect()
+    }
+
+    /// Resolves the referenced path to a global, the path may continue with field accesses.
+    ///
+    /// Returns `None` if the first scope defining the name does not define it as a global.
+    fn resolve(&self, node: &Node, path: &[&str]) -> Option<usize> {
+        let mut candidates = vec![(self.strs(&node.module), path)];
+        for import in node.imports {
+            let base = self.strs(&import.path.parts);
+            if import.glob {
+                candidates.push((base, path));
+                continue;
+            }
+            let name = import.alias.unwrap_or(*import.path.parts.last().unwrap());
+            if self.lex.slice(name) == path[0] {
+                candidates.push((base, &path[1..]));
+            }
+        }
+        candidates.push((Vec::with_capacity(0), path));
+        let (base, rest) = candidates.into_iter().find(|(base, rest)| {
+            // named imports only become candidates if they define the name
+            if rest.len() < path.len() {
+                return true;
+            }
+            let mut full = base.clone();
+            full.push(path[0]);
+            self.names.contains(&full.join("."))
+        })?;
+        // imports may name a global directly
+        (0..=rest.len()).rev().find_map(|len| {
+            let mut full = base.clone();
+            full.extend_from_slice(&rest[..len]);
+            if full.is_empty() {
+                return None;
+            }
+            self.by_path.get(&full.join(".")).copied()
+        })
+    }
+
+    fn visit(
+        &self,
+        index: usize,
+        deps: &[Vec<usize>],
+        marks: &mut [Mark],
+        order: &mut Vec<usize>,
+    ) -> Result<()> {
+        match marks[index] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                return Err(Error::InitializationCycle(self.nodes[index].global.name));
+            }
+            Mark::Unvisited => {}
+        }
+        marks[index] = Mark::Visiting;
+        for &dep in &deps[index] {
+            self.visit(dep, deps, marks, order)?;
+        }
+        marks[index] = Mark::Done;
+        order.push(index);
+        Ok(())
+    }
+
+    fn strs(&self, parts: &[Str]) -> Vec<&'l str> {
+        parts.iter().map(|part| self.lex.slice(*part)).collect()
+    }
+}
+
+/// Collects the paths referenced by an expression, excluding local variables.
+struct References<'l> {
+    lex: &'l Lexer,
+    locals: Vec<&'l str>,
+    paths: Vec<Vec<&'l str>>,
+}
+
+impl<'l> References<'l> {
+    fn path(&self, expr: &HirExpression) -> Option<Vec<&'l str>> {
+        match &expr.kind {
+            HirExpressionKind::Access { name } => Some(vec![self.lex.slice(*name)]),
+            HirExpressionKind::DotAccess { expr, name } => {
+                let mut path = self.path(expr)?;
+                path.push(self.lex.slice(*name));
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, expr: &HirExpression) {
+        if let Some(path) = self.path(expr) {
+            if !self.locals.contains(&path[0]) {
+                self.paths.push(path);
+            }
+            return;
+        }
+        match &expr.kind {
+            HirExpressionKind::Int { .. }
+            | HirExpressionKind::Float { .. }
+            | HirExpressionKind::String { .. }
+            | HirExpressionKind::Access { .. } => {}
+            HirExpressionKind::DotAccess { expr, .. }
+            | HirExpressionKind::SafeDotAccess { expr, .. }
+            | HirExpressionKind::AddressOf { expr, .. }
+            | HirExpressionKind::Deref { expr }
+            | HirExpressionKind::Try { expr }
+            | HirExpressionKind::Cast { expr, .. } => self.expr(expr),
+            HirExpressionKind::UnaryOp { arg, .. } => self.expr(arg),
+            HirExpressionKind::IndexAccess { expr, index } => {
+                self.expr(expr);
+                self.expr(index);
+            }
+            HirExpressionKind::Call { expr, args } => {
+                self.expr(expr);
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            HirExpressionKind::BinaryOp { args, .. } | HirExpressionKind::Coalesce { args } => {
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            HirExpressionKind::Block { block } | HirExpressionKind::Loop { block, .. } => {
+                self.block(block)
+            }
+            HirExpressionKind::If {
+                cond,
+                block,
+                else_block,
+            } => {
+                self.expr(cond);
+                self.block(block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            HirExpressionKind::Match { expr, arms } => {
+                self.expr(expr);
+                for arm in arms {
+                    let len = self.locals.len();
+                    self.pattern(&arm.pattern);
+                    self.expr(&arm.body);
+                    self.locals.truncate(len);
+                }
+            }
+        }
+    }
+
+    /// Adds the bindings of `pattern` to the locals.
+    fn pattern(&mut self, pattern: &HirPattern) {
+        match &pattern.kind {
+            HirPatternKind::Binding { name } => self.locals.push(self.lex.slice(*name)),
+            HirPatternKind::Variant { fields, .. } => {
+                fields.iter().for_each(|field| self.pattern(field))
+            }
+            HirPatternKind::Wildcard | HirPatternKind::Literal { .. } => {}
+        }
+    }
+
+    fn block(&mut self, block: &HirBlock) {
+        let len = self.locals.len();
+        block
+            .statements
+            .iter()
+            .for_each(|statement| self.statement(&statement.kind));
+        if let Some(tail) = &block.tail {
+            self.expr(tail);
+        }
+        self.locals.truncate(len);
+    }
+
+    fn statement(&mut self, statement: &HirStatementKind) {
+        match statement {
+            HirStatementKind::VarDecl { name, expr, .. }
+            | HirStatementKind::ConstDecl { name, expr, .. } => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+                self.locals.push(self.lex.slice(*name));
+            }
+            HirStatementKind::Assign { expr, value } => {
+                self.expr(expr);
+                self.expr(value);
+            }
+            HirStatementKind::If {
+                cond,
+                block,
+                else_block,
+            } => {
+                self.expr(cond);
+                self.block(block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            HirStatementKind::While { cond, block, .. } => {
+                self.expr(cond);
+                self.block(block);
+            }
+            HirStatementKind::For {
+                name, expr, block, ..
+            } => {
+                self.expr(expr);
+                let len = self.locals.len();
+                self.locals.push(self.lex.slice(*name));
+                self.block(block);
+                self.locals.truncate(len);
+            }
+            HirStatementKind::Loop { block, .. } => self.block(block),
+            HirStatementKind::Call { expr, args } => {
+                self.expr(expr);
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            HirStatementKind::Expr { expr } => self.expr(expr),
+            HirStatementKind::Return { expr } | HirStatementKind::Break { value: expr, .. } => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+            HirStatementKind::Defer { statement } => self.statement(&statement.kind),
+            HirStatementKind::Continue { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::hir::parser::Parser;
+    use crate::prelude::*;
+
+    use super::initialization_order;
+
+    fn order(source: &str) -> Result<Vec<String>> {
+        let mut parser = Parser::new(Rc::from(source));
+        parser.parse()?;
+        let order = initialization_order(&parser.ast, &parser.lex)?;
+        Ok(order
+            .iter()
+            .map(|init| {
+                let mut path = init
+                    .module
+                    .iter()
+                    .map(|part| parser.lex.slice(*part).to_string())
+                    .collect::<Vec<_>>();
+                path.push(parser.lex.slice(init.global.name).to_string());
+                path.join(".")
+            })
+            .collect())
+    }
+
+    #[test]
+    fn declaration_order() {
+        let source = r#"
+var a = 1;
+module util {
+    var c = 3;
+}
+var b: int;
+"#;
+        assert_eq!(order(source).unwrap(), ["a", "b", "util.c"]);
+    }
+
+    #[test]
+    fn dependencies_across_modules() {
+        let source = r#"
+import util.d as e;
+var a = b + util.c.x + e;
+var b = 1;
+module util {
+    var c = d;
+    var d = 2;
+}
+"#;
+        assert_eq!(order(source).unwrap(), ["b", "util.d", "util.c", "a"]);
+    }
+
+    #[test]
+    fn locals_shadow_globals() {
+        let source = r#"
+var a = {
+    const b = 1;
+    b
+};
+var b = a;
+"#;
+        assert_eq!(order(source).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn module_names_shadow_root_globals() {
+        let source = r#"
+var a = util.b;
+module util {
+    const a = 1;
+    var b = a;
+}
+"#;
+        assert_eq!(order(source).unwrap(), ["util.b", "a"]);
+        let source = r#"
+var a = util.b;
+module util {
+    fun a() -> int {
+        return 1;
+    }
+    var b = a();
+}
+"#;
+        assert_eq!(order(source).unwrap(), ["util.b", "a"]);
+    }
+
+    #[test]
+    fn cycle() {
+        let source = r#"
+var a = b;
+var b = { a + 1 };
+"#;
+        let mut parser = Parser::new(Rc::from(source));
+        parser.parse().unwrap();
+        let Err(Error::InitializationCycle(slice)) = initialization_order(&parser.ast, &parser.lex)
+        else {
+            panic!("expected an initialization cycle");
+        };
+        assert_eq!(parser.lex.slice(slice), "a");
+    }
+
+    #[test]
+    fn missing_type_or_initializer() {
+        let mut parser = Parser::new(Rc::from("var a;"));
+        assert!(matches!(
+            parser.parse(),
+            Err(Error::MissingTypeOrInitializer(_))
+        ));
+    }
+}
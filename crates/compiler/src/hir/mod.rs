@@ -1,23 +1,37 @@
 use crate::{lexer::Token, prelude::*};
 
+pub mod cfg;
+pub mod init;
 pub mod parser;
 
-#[derive(Debug)]
+/// The parsed source
+///
+/// Every node has a `span` covering its full source extent, including the attributes in front of
+/// it.
+#[derive(Debug, Default)]
 pub struct Hir {
+    pub root: HirModuleBody,
+}
+
+/// The items owned by a module.
+#[derive(Debug)]
+pub struct HirModuleBody {
     pub modules: Vec<HirModule>,
     pub imports: Vec<HirImport>,
     pub constants: Vec<HirConst>,
+    pub globals: Vec<HirGlobal>,
     pub types: Vec<HirTypeDecl>,
     pub impls: Vec<HirImpl>,
     pub functions: Vec<HirFunction>,
 }
 
-impl Default for Hir {
+impl Default for HirModuleBody {
     fn default() -> Self {
         Self {
             modules: Vec::with_capacity(0),
             imports: Vec::with_capacity(0),
             constants: Vec::with_capacity(0),
+            globals: Vec::with_capacity(0),
             types: Vec::with_capacity(0),
             impls: Vec::with_capacity(0),
             functions: Vec::with_capacity(0),
@@ -25,35 +39,142 @@ impl Default for Hir {
     }
 }
 
-#[derive(Debug)]
+/// `a.b.c`, `span` reaches from the first to the last part
+///
+/// The span of a path inside an import group like `import a:{b.c, d.e};` only covers the parts
+/// written in the group, `b.c` and `d.e`.
+#[derive(Clone, Debug)]
 pub struct HirPath {
+    pub span: Str,
     pub parts: Vec<Str>,
 }
 
+impl HirPath {
+    /// Creates a path of at least one part.
+    pub fn new(parts: Vec<Str>) -> Self {
+        Self::in_group(parts, 0)
+    }
+
+    /// Creates a path whose first `prefix` parts are written in front of an import group.
+    pub fn in_group(parts: Vec<Str>, prefix: usize) -> Self {
+        let (first, last) = (parts[prefix], parts[parts.len() - 1]);
+        Self {
+            span: Str(first.0, last.0 + last.1 - first.0),
+            parts,
+        }
+    }
+}
+
 // todo: generics
-#[derive(Debug)]
-pub enum HirType {
+#[derive(Clone, Debug)]
+pub struct HirType {
+    pub span: Str,
+    pub kind: HirTypeKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum HirTypeKind {
     SelfType,
-    Direct { path: HirPath },
-    Reference { r#type: Box<HirType> },
-    ConstReference { r#type: Box<HirType> },
+    Direct {
+        path: HirPath,
+    },
+    Reference {
+        r#type: Box<HirType>,
+    },
+    ConstReference {
+        r#type: Box<HirType>,
+    },
+    /// `type?`, either a value of `type` or null
+    Optional {
+        r#type: Box<HirType>,
+    },
+    /// `Self.Item`, the associated type `name` of `type`
+    ///
+    /// Paths starting with `Self` are parsed as projections, as are paths starting with the name of
+    /// an associated type of the enclosing trait or impl, `Item` there is `Self.Item` with an empty
+    /// span for `Self`. Other paths like `T.Item` are parsed as [`HirTypeKind::Direct`] and become
+    /// projections once name resolution finds `T` to be a type.
+    Projection {
+        r#type: Box<HirType>,
+        name: Str,
+    },
+}
+
+/// Where an item, field or member function is visible
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HirVisibility {
+    /// Only visible in the defining module and its submodules
+    Private,
+    /// `pub(module)`, also visible in the parent module and all of its submodules
+    Module,
+    /// `pub(package)`, visible in the whole package but not to other packages
+    Package,
+    /// `pub`, visible everywhere
+    Public,
+}
+
+/// `@name(args)` or `#[name(args), ...]`
+#[derive(Clone, Debug)]
+pub struct HirAttribute {
+    pub span: Str,
+    pub name: Str,
+    pub args: Vec<HirAttributeArg>,
+}
+
+/// `value` or `name = value` in the arguments of an attribute
+#[derive(Clone, Debug)]
+pub struct HirAttributeArg {
+    pub span: Str,
+    pub name: Option<Str>,
+    pub value: HirExpression,
 }
 
 #[derive(Debug)]
 pub struct HirModule {
+    pub span: Str,
     pub name: Str,
-    pub public: bool,
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
+    /// `module name { ... }`, `None` for `module name;` which is defined in another file
+    pub body: Option<HirModuleBody>,
 }
 
 #[derive(Debug)]
 pub struct HirImport {
+    /// The whole import statement, shared by all imports of a group `import a:{b, c};`
+    pub span: Str,
     pub path: HirPath,
+    /// Re-exports the imported item(s) from the importing module if not private
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
+    /// `import path as alias;`
+    pub alias: Option<Str>,
+    /// `import path.*;`, imports every item of `path` visible to the importing module
+    pub glob: bool,
 }
 
+/// `const name: type = expr;` at module level or as a member of a type, trait or impl
+///
+/// Trait members may omit `expr`, implementations of the trait then have to provide it.
 #[derive(Debug)]
 pub struct HirConst {
+    pub span: Str,
     pub name: Str,
-    pub public: bool,
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
+    pub r#type: Option<HirType>,
+    pub expr: Option<HirExpression>,
+}
+
+/// `var name: type = expr;` at module level, at least one of `type` and `expr` is given
+///
+/// See [`init::initialization_order`] for the order in which globals are initialized.
+#[derive(Debug)]
+pub struct HirGlobal {
+    pub span: Str,
+    pub name: Str,
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
     pub r#type: Option<HirType>,
     pub expr: Option<HirExpression>,
 }
@@ -62,77 +183,218 @@ pub struct HirConst {
 #[derive(Debug)]
 pub enum HirTypeDecl {
     Trait {
+        span: Str,
         name: Str,
-        public: bool,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+        /// `trait name : supertraits { ... }`, traits every implementing type must implement too
+        supertraits: Vec<HirType>,
+        /// Types without a target are abstract, types with a target are defaults
+        types: Vec<HirAssociatedType>,
+        /// Constants without a value are abstract, constants with a value are defaults
+        constants: Vec<HirConst>,
+        /// Functions without a body are abstract, functions with a body are default
+        /// implementations that impls may omit or override
         functions: Vec<HirFunction>,
     },
     Struct {
+        span: Str,
         name: Str,
-        public: bool,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
         fields: Vec<HirStructField>,
+        /// Associated constants, accessed as `Name.CONSTANT`
+        constants: Vec<HirConst>,
         functions: Vec<HirFunction>,
     },
     Enum {
+        span: Str,
         name: Str,
-        public: bool,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
         variants: Vec<HirEnumVariant>,
+        /// Associated constants, accessed as `Name.CONSTANT`
+        constants: Vec<HirConst>,
         functions: Vec<HirFunction>,
     },
+    /// `type name = target;`
+    Alias {
+        span: Str,
+        name: Str,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+        target: HirType,
+    },
+}
+
+/// `type name : bounds = target;` in a trait or `type name = target;` in an impl
+#[derive(Debug)]
+pub struct HirAssociatedType {
+    pub span: Str,
+    pub name: Str,
+    pub attributes: Vec<HirAttribute>,
+    /// Traits the target of every implementation must implement, empty in impls
+    pub bounds: Vec<HirType>,
+    /// `None` for abstract types of a trait, always given in impls
+    pub target: Option<HirType>,
 }
 
 // todo: generics
 #[derive(Debug)]
 pub struct HirImpl {
+    pub span: Str,
+    pub attributes: Vec<HirAttribute>,
     pub target: HirType,
-    pub r#trait: HirType,
+    /// `impl target : trait { ... }`, `None` for inherent impls (`impl target { ... }`)
+    pub r#trait: Option<HirType>,
+    pub types: Vec<HirAssociatedType>,
+    pub constants: Vec<HirConst>,
     pub functions: Vec<HirFunction>,
 }
 
 // todo: generics
 #[derive(Debug)]
 pub struct HirFunction {
+    pub span: Str,
     pub name: Str,
-    pub public: bool,
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
+    /// `self`, `*self` or `*const self`, `None` for associated functions
+    pub receiver: Option<HirReceiver>,
     pub params: Vec<HirFunctionParam>,
     pub return_type: Option<HirType>,
     pub body: Option<HirBlock>,
+    /// `extern "abi" fun ...`, a function using a foreign calling convention
+    pub r#extern: Option<HirExtern>,
+}
+
+/// A foreign function, it is imported from another library if it has no body and exported
+/// otherwise
+///
+/// `extern` is only accepted at module level, functions of types, traits and impls cannot be
+/// foreign. The strings are the contents between the quotes with escape sequences as written.
+#[derive(Debug)]
+pub struct HirExtern {
+    /// `extern "abi"`, shared by the functions of an extern block
+    pub span: Str,
+    /// The calling convention, `C` if omitted
+    pub abi: Option<Str>,
+    /// The argument of `@link_name("...")`, the symbol name if it differs from the function name
+    pub link_name: Option<Str>,
+}
+
+impl HirTypeDecl {
+    pub fn span(&self) -> Str {
+        match self {
+            HirTypeDecl::Trait { span, .. }
+            | HirTypeDecl::Struct { span, .. }
+            | HirTypeDecl::Enum { span, .. }
+            | HirTypeDecl::Alias { span, .. } => *span,
+        }
+    }
+
+    pub fn name(&self) -> Str {
+        match self {
+            HirTypeDecl::Trait { name, .. }
+            | HirTypeDecl::Struct { name, .. }
+            | HirTypeDecl::Enum { name, .. }
+            | HirTypeDecl::Alias { name, .. } => *name,
+        }
+    }
+}
+
+impl HirConst {
+    /// Whether the constant has no value (`const name: type;`), only valid in traits.
+    pub fn is_abstract(&self) -> bool {
+        self.expr.is_none()
+    }
+}
+
+impl HirFunction {
+    /// Whether the function has no body (`fun name();`).
+    pub fn is_abstract(&self) -> bool {
+        self.body.is_none()
+    }
+
+    /// Whether the function takes a receiver, otherwise it is an associated function.
+    pub fn is_method(&self) -> bool {
+        self.receiver.is_some()
+    }
+}
+
+/// The receiver of a method, written in front of its parameters
+#[derive(Clone, Copy, Debug)]
+pub struct HirReceiver {
+    pub span: Str,
+    pub kind: HirReceiverKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HirReceiverKind {
+    /// `self`
+    Value,
+    /// `*self`
+    Reference,
+    /// `*const self`
+    ConstReference,
 }
 
 #[derive(Debug)]
 pub struct HirStructField {
+    pub span: Str,
     pub name: Str,
-    pub public: bool,
+    pub visibility: HirVisibility,
+    pub attributes: Vec<HirAttribute>,
     pub r#type: HirType,
 }
 
 #[derive(Debug)]
 pub enum HirEnumVariant {
     Empty {
+        span: Str,
         name: Str,
+        attributes: Vec<HirAttribute>,
     },
     Tuple {
+        span: Str,
         name: Str,
+        attributes: Vec<HirAttribute>,
         types: Vec<HirType>,
     },
     Struct {
+        span: Str,
         name: Str,
+        attributes: Vec<HirAttribute>,
         fields: Vec<HirStructField>,
     },
 }
 
 #[derive(Debug)]
 pub struct HirFunctionParam {
+    pub span: Str,
     pub name: Str,
+    pub attributes: Vec<HirAttribute>,
     pub r#type: HirType,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HirBlock {
+    pub span: Str,
     pub statements: Vec<HirStatement>,
+    /// The trailing expression without a semicolon, it is the value of the block
+    pub tail: Option<Box<HirExpression>>,
 }
 
-#[derive(Debug)]
-pub enum HirStatement {
+/// A statement and the attributes in front of it
+#[derive(Clone, Debug)]
+pub struct HirStatement {
+    pub span: Str,
+    pub attributes: Vec<HirAttribute>,
+    pub kind: HirStatementKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum HirStatementKind {
     /// `var name: type = expr;`
     VarDecl {
         name: Str,
@@ -156,33 +418,89 @@ pub enum HirStatement {
         block: HirBlock,
         else_block: Option<HirBlock>,
     },
-    /// `while cond block`
+    /// `'label: while cond block`, labels are stored without the `'`
     While {
+        label: Option<Str>,
         cond: HirExpression,
         block: HirBlock,
     },
-    /// `for name in expr block`
+    /// `'label: for name in expr block`
     For {
+        label: Option<Str>,
         name: Str,
         expr: HirExpression,
         block: HirBlock,
     },
+    /// `'label: loop block`
+    Loop { label: Option<Str>, block: HirBlock },
     // todo: generics
     /// `expr(args);`
     Call {
         expr: HirExpression,
         args: Vec<HirExpression>,
     },
+    /// `expr;`
+    Expr { expr: HirExpression },
     /// `return expr;`
-    Return { expr: HirExpression },
-    /// `continue;`
-    Continue,
-    /// `break;`
-    Break,
+    Return { expr: Option<HirExpression> },
+    /// `defer statement` or `defer block`
+    ///
+    /// The statement runs when the enclosing block is left, on every exit path: reaching the end of
+    /// the block, `return`, `break`, `continue` and `?`. Deferred statements of a block run in
+    /// reverse order of their `defer` statements, only those whose `defer` statement was reached
+    /// run. When a jump leaves several blocks, the deferred statements of the innermost block run
+    /// first. The statement must not jump out of itself with `return`, `break`, `continue` or `?`.
+    Defer { statement: Box<HirStatement> },
+    /// `continue 'label;`
+    Continue { label: Option<Str> },
+    /// `break 'label value;`, the innermost loop is targeted if no label is given
+    ///
+    /// A value may only be given when breaking out of a `loop`, it becomes the value of the loop
+    /// expression.
+    Break {
+        label: Option<Str>,
+        value: Option<HirExpression>,
+    },
 }
 
-#[derive(Debug)]
-pub enum HirExpression {
+/// `pattern => body`
+#[derive(Clone, Debug)]
+pub struct HirMatchArm {
+    pub span: Str,
+    pub pattern: HirPattern,
+    pub body: HirExpression,
+}
+
+#[derive(Clone, Debug)]
+pub struct HirPattern {
+    pub span: Str,
+    pub kind: HirPatternKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum HirPatternKind {
+    /// `_`, matches every value
+    Wildcard,
+    /// `name`, matches every value and binds it to `name` in the body of the arm
+    Binding { name: Str },
+    /// An integer, float or string literal, integers and floats may be negated
+    Literal { value: HirExpression },
+    /// `Enum.variant` or `Enum.variant(fields)`, matches the variant if `fields` match its values
+    Variant {
+        path: HirPath,
+        fields: Vec<HirPattern>,
+    },
+}
+
+/// An expression, parentheses around it are part of its span
+#[derive(Clone, Debug)]
+pub struct HirExpression {
+    pub span: Str,
+    pub kind: HirExpressionKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum HirExpressionKind {
     Int {
         slice: Str,
     },
@@ -201,6 +519,11 @@ pub enum HirExpression {
         expr: Box<HirExpression>,
         name: Str,
     },
+    /// `expr?.name`, null if `expr` is null
+    SafeDotAccess {
+        expr: Box<HirExpression>,
+        name: Str,
+    },
     /// `expr[index]`
     IndexAccess {
         expr: Box<HirExpression>,
@@ -212,7 +535,16 @@ pub enum HirExpression {
         expr: Box<HirExpression>,
         args: Vec<HirExpression>,
     },
-    /// `op arg`
+    /// `&expr` (`*T`) or `&const expr` (`*const T`)
+    AddressOf {
+        mutable: bool,
+        expr: Box<HirExpression>,
+    },
+    /// `*expr`
+    Deref {
+        expr: Box<HirExpression>,
+    },
+    /// `op arg`, `op` is neither `&` nor `*`
     UnaryOp {
         op: Token,
         arg: Box<HirExpression>,
@@ -222,4 +554,41 @@ pub enum HirExpression {
         op: Token,
         args: Box<[HirExpression; 2]>,
     },
+    /// `expr?`, returns the `err` variant of a result from the enclosing function, otherwise it is
+    /// the value of the `ok` variant
+    ///
+    /// `expr` must be a result and the return type of the enclosing function must be a result with
+    /// the same error type, see DESIGN.md for the shape of a result.
+    Try {
+        expr: Box<HirExpression>,
+    },
+    /// `args[0] ?? args[1]`, `args[1]` is only evaluated if `args[0]` is null
+    Coalesce {
+        args: Box<[HirExpression; 2]>,
+    },
+    /// `block`, the value is given by the tail expression of the block
+    Block {
+        block: HirBlock,
+    },
+    /// `if cond block else else_block`
+    If {
+        cond: Box<HirExpression>,
+        block: HirBlock,
+        else_block: Option<HirBlock>,
+    },
+    /// `'label: loop block`, the value is given by `break value;`
+    Loop {
+        label: Option<Str>,
+        block: HirBlock,
+    },
+    /// `match expr { arms }`, the value is given by the first arm whose pattern matches
+    Match {
+        expr: Box<HirExpression>,
+        arms: Vec<HirMatchArm>,
+    },
+    /// `expr as type`
+    Cast {
+        expr: Box<HirExpression>,
+        r#type: HirType,
+    },
 }
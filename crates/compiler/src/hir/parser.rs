@@ -1,22 +1,42 @@
 use std::rc::Rc;
 
-use crate::hir::{HirEnumVariant, HirImpl, HirStructField, HirTypeDecl};
+use crate::hir::{
+    HirAssociatedType, HirAttribute, HirAttributeArg, HirEnumVariant, HirExtern, HirImpl,
+    HirReceiver, HirReceiverKind, HirStructField, HirTypeDecl, HirVisibility,
+};
 use crate::{
-    hir::{HirConst, HirFunction},
-    lexer::{Lexer, Token, TokenType},
+    error::{Context, ContextKind, Expected},
+    hir::{HirConst, HirFunction, HirGlobal},
+    lexer::{Associativity, Lexer, Token, TokenType},
     prelude::*,
 };
 
 use super::{
-    Hir, HirBlock, HirExpression, HirFunctionParam, HirImport, HirModule, HirPath, HirStatement,
-    HirType,
+    Hir, HirBlock, HirExpression, HirExpressionKind, HirFunctionParam, HirImport, HirMatchArm,
+    HirModule, HirModuleBody, HirPath, HirPattern, HirPatternKind, HirStatement, HirStatementKind,
+    HirType, HirTypeKind,
 };
 
 pub struct Parser {
+    /// Every error found by [`Parser::parse`] in source order
     pub errors: Vec<Error>,
     pub lex: Lexer,
     pub ast: Hir,
     pub peek_buf: Option<Token>,
+    /// Keywords and labels of the enclosing loops, innermost last, `None` for unlabeled loops
+    loops: Vec<(TokenType, Option<Str>)>,
+    /// Number of enclosing loops at the start of the innermost deferred statement
+    defer_loops: Option<usize>,
+    /// Associated types of the trait or impl being parsed, their bare names are `Self` projections
+    associated_types: Vec<Str>,
+    /// End of the last consumed token
+    last_end: u32,
+    /// Number of consumed `{` without their closing `}`
+    depth: u32,
+    /// Tokens that were looked for at the peeked token
+    expected: Vec<Expected>,
+    /// Items and members being parsed, innermost last
+    context: Vec<Context>,
 }
 
 impl Parser {
@@ -26,43 +46,80 @@ impl Parser {
             lex: Lexer::new(source),
             ast: Hir::default(),
             peek_buf: None,
+            loops: Vec::with_capacity(0),
+            defer_loops: None,
+            associated_types: Vec::with_capacity(0),
+            last_end: 0,
+            depth: 0,
+            expected: Vec::with_capacity(0),
+            context: Vec::with_capacity(0),
         }
     }
 
+    /// Consumes the next token if it has the type `token_type`, leaves it for error recovery
+    /// otherwise.
     pub fn expect(&mut self, token_type: TokenType) -> Result<Token> {
-        let tok = if let Some(tok_buf) = self.peek_buf.take() {
-            tok_buf
-        } else {
-            self.lex.next_token()?.ok_or(Error::UnexpectedEof)?
-        };
+        self.expecting(&[token_type]);
+        let tok = self.peek()?;
         if tok.r#type != token_type {
-            return Err(Error::UnexpectedToken(tok.slice));
+            return Err(self.unexpected(tok, &[]));
+        }
+        self.expect_one()
+    }
+
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let tok = match self.peek_buf.take() {
+            Some(tok) => Some(tok),
+            None => self.lex.next_token()?,
+        };
+        if let Some(tok) = tok {
+            self.last_end = tok.slice.0 + tok.slice.1;
+            self.expected.clear();
+            match tok.r#type {
+                TokenType::LeftBrace => self.depth += 1,
+                TokenType::RightBrace => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
         }
         Ok(tok)
     }
 
     pub fn expect_one(&mut self) -> Result<Token> {
-        if let Some(tok) = self.peek_buf.take() {
-            return Ok(tok);
+        match self.next_token()? {
+            Some(tok) => Ok(tok),
+            None => Err(self.unexpected_eof()),
         }
-        self.lex
-            .next_token()
-            .and_then(|it| it.ok_or(Error::UnexpectedEof))
+    }
+
+    /// Returns the slice from `start` to the end of the last consumed token.
+    pub fn span_from(&self, start: u32) -> Str {
+        Str(start, self.last_end - start)
     }
 
     pub fn peek(&mut self) -> Result<Token> {
         if let Some(tok) = self.peek_buf {
             return Ok(tok);
         }
-        let tok = self
-            .lex
-            .next_token()
-            .and_then(|it| it.ok_or(Error::UnexpectedEof))?;
+        let Some(tok) = self.lex.next_token()? else {
+            return Err(self.unexpected_eof());
+        };
         self.peek_buf = Some(tok);
         Ok(tok)
     }
 
+    /// Returns the `n`th token after the peeked token without consuming any token.
+    pub fn peek_ahead(&mut self, n: usize) -> Result<Option<Token>> {
+        self.peek()?;
+        let mut lex = self.lex.clone();
+        let mut tok = None;
+        for _ in 0..n {
+            tok = lex.next_token()?;
+        }
+        Ok(tok)
+    }
+
     pub fn maybe(&mut self, token_type: TokenType) -> Result<Option<Token>> {
+        self.expecting(&[token_type]);
         let tok = self.peek()?;
         Ok(if tok.r#type == token_type {
             self.expect_one()?;
@@ -72,210 +129,897 @@ impl Parser {
         })
     }
 
-    pub fn parse(&mut self) -> Result<()> {
-        while let Some(tok) = self.lex.next_token()? {
+    /// Records that the tokens `expected` may appear at the peeked token.
+    fn expecting(&mut self, expected: &[TokenType]) {
+        for token_type in expected {
+            self.expecting_one(Expected::Token(*token_type));
+        }
+    }
+
+    /// Records that `expected` may appear at the peeked token.
+    fn expecting_one(&mut self, expected: Expected) {
+        if !self.expected.contains(&expected) {
+            self.expected.push(expected);
+        }
+    }
+
+    /// The error for the token `found`, `expected` adds to the tokens looked for at it.
+    fn unexpected(&mut self, found: Token, expected: &[TokenType]) -> Error {
+        self.expecting(expected);
+        Error::UnexpectedToken {
+            found,
+            expected: self.expected.clone(),
+            context: Vec::with_capacity(0),
+        }
+    }
+
+    fn unexpected_eof(&self) -> Error {
+        Error::UnexpectedEof {
+            at: self.lex.end(),
+            expected: self.expected.clone(),
+            context: Vec::with_capacity(0),
+        }
+    }
+
+    /// Runs `parse` inside a context of `kind`, errors leaving it record the context.
+    fn in_context<T>(
+        &mut self,
+        kind: ContextKind,
+        parse: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        self.context.push(Context { kind, name: None });
+        let result = parse(self);
+        let context = self.context.pop().unwrap();
+        result.map_err(|err| err.in_context(context))
+    }
+
+    /// Parses the members of a trait or impl after its `{`, bare names of the associated types it
+    /// declares refer to the associated types of `Self`.
+    fn in_associated_scope(&mut self, parse: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let names = self.scan_associated_types();
+        let outer = std::mem::replace(&mut self.associated_types, names);
+        let result = parse(self);
+        self.associated_types = outer;
+        result
+    }
+
+    /// Returns the names of the associated types declared before the `}` matching the consumed
+    /// `{` without consuming any token, so that members may use types declared after them.
+    fn scan_associated_types(&self) -> Vec<Str> {
+        let mut lex = self.lex.clone();
+        let mut names = Vec::with_capacity(0);
+        let mut depth = 0;
+        let mut prev = None;
+        // errors are reported once the members are parsed
+        while let Ok(Some(tok)) = lex.next_token() {
             match tok.r#type {
-                TokenType::KwPub => {
-                    let next = self.expect_one()?;
-                    match next.r#type {
-                        TokenType::KwModule => self.parse_root_module(true)?,
-                        TokenType::KwConst => self.parse_root_const(true)?,
-                        TokenType::KwTrait => self.parse_root_trait(true)?,
-                        TokenType::KwStruct => self.parse_root_struct(true)?,
-                        TokenType::KwEnum => self.parse_root_enum(true)?,
-                        TokenType::KwFun => self.parse_root_function(true)?,
-                        _ => return Err(Error::UnexpectedToken(next.slice)),
-                    }
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace if depth == 0 => break,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::Identifier if depth == 0 && prev == Some(TokenType::KwType) => {
+                    names.push(tok.slice)
                 }
-                TokenType::KwModule => self.parse_root_module(false)?,
-                TokenType::KwImport => {
-                    self.parse_root_import(&mut Vec::with_capacity(1))?;
-                    self.expect(TokenType::Semicolon)?;
+                _ => {}
+            }
+            prev = Some(tok.r#type);
+        }
+        names
+    }
+
+    /// Names the innermost context once its name is parsed.
+    fn name_context(&mut self, name: Str) {
+        if let Some(context) = self.context.last_mut() {
+            context.name = Some(name);
+        }
+    }
+
+    /// Parses the whole source into `ast`, recovering from errors at item and statement
+    /// boundaries.
+    ///
+    /// Every error is collected in `errors` and the first one is returned, `ast` then contains
+    /// everything that could be parsed.
+    pub fn parse(&mut self) -> Result<()> {
+        let mut root = std::mem::take(&mut self.ast.root);
+        self.parse_module_body(&mut root, false);
+        self.ast.root = root;
+        match self.errors.first() {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Records `err` with the contexts it is reported in, unless it repeats the last recorded
+    /// error or the end of the source was already reported.
+    fn report(&mut self, mut err: Error) {
+        let eof = |err: &Error| matches!(err, Error::UnexpectedEof { .. });
+        if eof(&err) && self.errors.iter().any(eof) {
+            return;
+        }
+        for context in self.context.iter().rev() {
+            err = err.in_context(*context);
+        }
+        if self.errors.last() != Some(&err) {
+            self.errors.push(err);
+        }
+    }
+
+    /// Records `err` and skips to the next boundary at brace depth `depth`, `mark` is the value
+    /// of `last_end` before the failed item or statement.
+    ///
+    /// At least one token is skipped if the failed item or statement did not consume any, so
+    /// that parsing always makes progress.
+    fn recover(&mut self, err: Error, mark: u32, depth: u32, starts: fn(TokenType) -> bool) {
+        self.report(err);
+        if self.last_end == mark
+            && !matches!(self.peek(), Ok(tok) if tok.r#type == TokenType::RightBrace)
+        {
+            if let Err(err) = self.next_token() {
+                self.report(err);
+            }
+        }
+        self.synchronize(depth, starts);
+    }
+
+    /// Skips tokens until the end of the source, after a `;` or a closed `{ ... }` at brace depth
+    /// `depth`, or before a `}` or a token accepted by `starts` at brace depth `depth`.
+    fn synchronize(&mut self, depth: u32, starts: fn(TokenType) -> bool) {
+        while self.depth >= depth {
+            let tok = match self.peek() {
+                Ok(tok) => tok,
+                Err(Error::UnexpectedEof { .. }) => return,
+                Err(err) => {
+                    self.report(err);
+                    continue;
+                }
+            };
+            if self.depth == depth && (tok.r#type == TokenType::RightBrace || starts(tok.r#type)) {
+                return;
+            }
+            let _ = self.next_token();
+            if self.depth == depth
+                && matches!(tok.r#type, TokenType::Semicolon | TokenType::RightBrace)
+            {
+                return;
+            }
+        }
+    }
+
+    /// Parses module items until the end of the source or, for inline modules, until the closing
+    /// brace.
+    fn parse_module_body(&mut self, body: &mut HirModuleBody, inline: bool) {
+        let mut attributes = Vec::with_capacity(0);
+        let mut start = None;
+        loop {
+            let depth = self.depth;
+            let mark = self.last_end;
+            let tok = match self.next_token() {
+                Ok(Some(tok)) => tok,
+                Ok(None) => break,
+                Err(err) => {
+                    self.report(err);
+                    continue;
+                }
+            };
+            if matches!(tok.r#type, TokenType::At | TokenType::Hashtag) {
+                start.get_or_insert(tok.slice.0);
+                if let Err(err) = self.parse_attribute(tok, &mut attributes) {
+                    self.recover(err, mark, depth, is_item_start);
+                }
+                continue;
+            }
+            let attributes = std::mem::take(&mut attributes);
+            let start = start.take().unwrap_or(tok.slice.0);
+            if inline && tok.r#type == TokenType::RightBrace {
+                if !attributes.is_empty() {
+                    let err = self.unexpected(tok, ITEM_KEYWORDS);
+                    self.report(err);
                 }
-                TokenType::KwConst => self.parse_root_const(false)?,
-                TokenType::KwTrait => self.parse_root_trait(false)?,
-                TokenType::KwStruct => self.parse_root_struct(false)?,
-                TokenType::KwEnum => self.parse_root_enum(false)?,
-                TokenType::KwImpl => self.parse_root_impl()?,
-                TokenType::KwFun => self.parse_root_function(false)?,
-                _ => {
-                    eprintln!(
-                        "UNHANDLED TOKEN: {:?} {:?}",
-                        tok.r#type,
-                        self.lex.slice(tok.slice)
-                    );
+                return;
+            }
+            if let Err(err) = self.parse_item(body, start, tok, attributes) {
+                self.recover(err, mark, depth, is_item_start);
+            }
+        }
+        if !attributes.is_empty() {
+            self.expecting(ITEM_KEYWORDS);
+        } else if inline {
+            self.expecting(&[TokenType::RightBrace]);
+        }
+        if inline || !attributes.is_empty() {
+            self.report(self.unexpected_eof());
+        }
+    }
+
+    /// Parses the item starting with the already consumed `tok` into `body`, `start` is where its
+    /// attributes start.
+    fn parse_item(
+        &mut self,
+        body: &mut HirModuleBody,
+        start: u32,
+        tok: Token,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<()> {
+        let (visibility, tok) = if tok.r#type == TokenType::KwPub {
+            (self.parse_visibility_scope()?, self.expect_one()?)
+        } else {
+            (HirVisibility::Private, tok)
+        };
+        match tok.r#type {
+            TokenType::KwModule => {
+                let module = self.in_context(ContextKind::Module, |this| {
+                    this.parse_root_module(start, visibility, attributes)
+                })?;
+                body.modules.push(module);
+            }
+            TokenType::KwImport => {
+                let mut imports = Vec::with_capacity(1);
+                self.in_context(ContextKind::Import, |this| {
+                    this.parse_root_import(&mut Vec::with_capacity(1), 0, &mut imports)?;
+                    this.expect(TokenType::Semicolon)
+                })?;
+                let span = self.span_from(start);
+                body.imports
+                    .extend(imports.into_iter().map(|(path, alias, glob)| HirImport {
+                        span,
+                        path,
+                        visibility,
+                        attributes: attributes.clone(),
+                        alias,
+                        glob,
+                    }));
+            }
+            TokenType::KwConst => {
+                let constant = self.in_context(ContextKind::Constant, |this| {
+                    this.parse_const(start, visibility, attributes, false)
+                })?;
+                body.constants.push(constant);
+            }
+            TokenType::KwVar => {
+                let global = self.in_context(ContextKind::Global, |this| {
+                    this.parse_root_var(start, visibility, attributes)
+                })?;
+                body.globals.push(global);
+            }
+            TokenType::KwTrait => {
+                let decl = self.in_context(ContextKind::Trait, |this| {
+                    this.parse_root_trait(start, visibility, attributes)
+                })?;
+                body.types.push(decl);
+            }
+            TokenType::KwType => {
+                let decl = self.in_context(ContextKind::TypeAlias, |this| {
+                    this.parse_root_type(start, visibility, attributes)
+                })?;
+                body.types.push(decl);
+            }
+            TokenType::KwStruct => {
+                let decl = self.in_context(ContextKind::Struct, |this| {
+                    this.parse_root_struct(start, visibility, attributes)
+                })?;
+                body.types.push(decl);
+            }
+            TokenType::KwEnum => {
+                let decl = self.in_context(ContextKind::Enum, |this| {
+                    this.parse_root_enum(start, visibility, attributes)
+                })?;
+                body.types.push(decl);
+            }
+            TokenType::KwImpl => {
+                if visibility != HirVisibility::Private {
+                    return Err(Error::VisibilityNotAllowed(tok.slice));
                 }
+                let imp = self.in_context(ContextKind::Impl, |this| {
+                    this.parse_root_impl(start, attributes)
+                })?;
+                body.impls.push(imp);
             }
+            TokenType::KwFun => {
+                let function = self.in_context(ContextKind::Function, |this| {
+                    this.parse_function(start, visibility, attributes)
+                })?;
+                body.functions.push(function);
+            }
+            TokenType::KwExtern => self.in_context(ContextKind::Extern, |this| {
+                this.parse_root_extern(start, tok, visibility, attributes, &mut body.functions)
+            })?,
+            _ => return Err(self.unexpected(tok, ITEM_KEYWORDS)),
         }
         Ok(())
     }
 
-    fn parse_root_module(&mut self, public: bool) -> Result<()> {
+    fn parse_root_module(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirModule> {
         let name = self.expect(TokenType::Identifier)?.slice;
-        self.expect(TokenType::Semicolon)?;
-        self.ast.modules.push(HirModule { name, public });
-        Ok(())
+        self.name_context(name);
+        let body = if self.maybe(TokenType::LeftBrace)?.is_some() {
+            let mut body = HirModuleBody::default();
+            self.parse_module_body(&mut body, true);
+            Some(body)
+        } else {
+            self.expect(TokenType::Semicolon)?;
+            None
+        };
+        Ok(HirModule {
+            span: self.span_from(start),
+            name,
+            visibility,
+            attributes,
+            body,
+        })
     }
 
-    fn parse_root_import(&mut self, buf: &mut Vec<Str>) -> Result<()> {
+    /// Parses the path(s) of an import into `imports` as `(path, alias, glob)`, the first `prefix`
+    /// parts of `buf` are in front of the enclosing import group.
+    fn parse_root_import(
+        &mut self,
+        buf: &mut Vec<Str>,
+        prefix: usize,
+        imports: &mut Vec<(HirPath, Option<Str>, bool)>,
+    ) -> Result<()> {
         let root = self.expect(TokenType::Identifier)?;
         buf.push(root.slice);
         let next = self.peek()?;
         match next.r#type {
             TokenType::Dot => {
                 self.expect_one()?;
-                self.parse_root_import(buf)?;
+                if self.maybe(TokenType::Star)?.is_some() {
+                    imports.push((HirPath::in_group(buf.clone(), prefix), None, true));
+                } else {
+                    self.parse_root_import(buf, prefix, imports)?;
+                }
             }
             TokenType::Colon => {
                 self.expect_one()?;
-                self.parse_import_group(buf)?;
+                self.parse_import_group(buf, imports)?;
+            }
+            _ => {
+                let alias = if self.maybe(TokenType::KwAs)?.is_some() {
+                    Some(self.expect(TokenType::Identifier)?.slice)
+                } else {
+                    None
+                };
+                imports.push((HirPath::in_group(buf.clone(), prefix), alias, false));
             }
-            _ => self.ast.imports.push(HirImport {
-                path: HirPath { parts: buf.clone() },
-            }),
         }
         buf.pop().unwrap();
         Ok(())
     }
 
-    fn parse_root_const(&mut self, public: bool) -> Result<()> {
-        let (name, r#type, expr) = self.parse_var_decl()?;
-        self.ast.constants.push(HirConst {
+    /// Parses a constant after the `const` keyword, only constants of traits may omit the value.
+    fn parse_const(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+        allow_abstract: bool,
+    ) -> Result<HirConst> {
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
+        let (r#type, expr) = self.parse_var_decl()?;
+        if expr.is_none() && !allow_abstract {
+            return Err(Error::MissingInitializer(name));
+        }
+        Ok(HirConst {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
             r#type,
             expr,
-        });
-        Ok(())
+        })
+    }
+
+    fn parse_root_var(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirGlobal> {
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
+        let (r#type, expr) = self.parse_var_decl()?;
+        if r#type.is_none() && expr.is_none() {
+            return Err(Error::MissingTypeOrInitializer(name));
+        }
+        Ok(HirGlobal {
+            span: self.span_from(start),
+            name,
+            visibility,
+            attributes,
+            r#type,
+            expr,
+        })
+    }
+
+    /// Parses an associated type after the `type` keyword, only traits may declare bounds or
+    /// omit the target.
+    fn parse_associated_type(
+        &mut self,
+        start: u32,
+        attributes: Vec<HirAttribute>,
+        in_trait: bool,
+    ) -> Result<HirAssociatedType> {
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
+        let mut bounds = Vec::with_capacity(0);
+        if in_trait && self.maybe(TokenType::Colon)?.is_some() {
+            loop {
+                bounds.push(self.parse_type()?);
+                if self.maybe(TokenType::Comma)?.is_none() {
+                    break;
+                }
+            }
+        }
+        let target = if self.maybe(TokenType::Equal)?.is_some() {
+            Some(self.parse_type()?)
+        } else if in_trait {
+            None
+        } else {
+            return Err(Error::MissingTypeTarget(name));
+        };
+        self.expect(TokenType::Semicolon)?;
+        Ok(HirAssociatedType {
+            span: self.span_from(start),
+            name,
+            attributes,
+            bounds,
+            target,
+        })
     }
 
-    fn parse_root_trait(&mut self, public: bool) -> Result<()> {
+    fn parse_root_trait(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirTypeDecl> {
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         // todo: generics
+        let mut supertraits = Vec::with_capacity(0);
+        if self.maybe(TokenType::Colon)?.is_some() {
+            loop {
+                supertraits.push(self.parse_type()?);
+                if self.maybe(TokenType::Comma)?.is_none() {
+                    break;
+                }
+            }
+        }
         self.expect(TokenType::LeftBrace)?;
+        let mut types = Vec::with_capacity(0);
+        let mut constants = Vec::with_capacity(0);
         let mut functions = Vec::with_capacity(0);
-        while self.maybe(TokenType::RightBrace)?.is_none() {
-            self.expect(TokenType::KwFun)?;
-            functions.push(self.parse_function(false)?);
-        }
-        self.ast.types.push(HirTypeDecl::Trait {
+        self.in_associated_scope(|this| {
+            while this.maybe(TokenType::RightBrace)?.is_none() {
+                let member_start = this.peek()?.slice.0;
+                let attributes = this.parse_attributes()?;
+                if this.maybe(TokenType::KwType)?.is_some() {
+                    types.push(this.in_context(ContextKind::AssociatedType, |this| {
+                        this.parse_associated_type(member_start, attributes, true)
+                    })?);
+                    continue;
+                }
+                if this.maybe(TokenType::KwConst)?.is_some() {
+                    constants.push(this.in_context(ContextKind::Constant, |this| {
+                        this.parse_const(member_start, HirVisibility::Public, attributes, true)
+                    })?);
+                    continue;
+                }
+                this.expect(TokenType::KwFun)?;
+                functions.push(this.in_context(ContextKind::Function, |this| {
+                    this.parse_function(member_start, HirVisibility::Public, attributes)
+                })?);
+            }
+            Ok(())
+        })?;
+        Ok(HirTypeDecl::Trait {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
+            supertraits,
+            types,
+            constants,
             functions,
-        });
-        Ok(())
+        })
     }
 
-    fn parse_root_struct(&mut self, public: bool) -> Result<()> {
+    fn parse_root_struct(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirTypeDecl> {
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         // todo: generics
         self.expect(TokenType::LeftBrace)?;
         let mut fields = Vec::with_capacity(0);
+        let mut constants = Vec::with_capacity(0);
         let mut functions = Vec::with_capacity(0);
         while self.maybe(TokenType::RightBrace)?.is_none() {
-            if self.maybe(TokenType::KwPub)?.is_some() {
-                if self.maybe(TokenType::KwFun)?.is_some() {
-                    functions.push(self.parse_function(true)?);
-                    continue;
-                }
-                fields.push(self.parse_struct_field(true)?);
+            let member_start = self.peek()?.slice.0;
+            let attributes = self.parse_attributes()?;
+            let visibility = self.parse_visibility()?;
+            if self.maybe(TokenType::KwFun)?.is_some() {
+                functions.push(self.in_context(ContextKind::Function, |this| {
+                    this.parse_function(member_start, visibility, attributes)
+                })?);
                 continue;
             }
-            if self.maybe(TokenType::KwFun)?.is_some() {
-                functions.push(self.parse_function(false)?);
+            if self.maybe(TokenType::KwConst)?.is_some() {
+                constants.push(self.in_context(ContextKind::Constant, |this| {
+                    this.parse_const(member_start, visibility, attributes, false)
+                })?);
                 continue;
             }
-            fields.push(self.parse_struct_field(false)?);
+            fields.push(self.in_context(ContextKind::StructField, |this| {
+                this.parse_struct_field(member_start, visibility, attributes)
+            })?);
         }
-        self.ast.types.push(HirTypeDecl::Struct {
+        Ok(HirTypeDecl::Struct {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
             fields,
+            constants,
             functions,
-        });
-        Ok(())
+        })
     }
 
-    fn parse_root_enum(&mut self, public: bool) -> Result<()> {
+    fn parse_root_enum(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirTypeDecl> {
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         // todo: generics
         self.expect(TokenType::LeftBrace)?;
         let mut variants = Vec::with_capacity(0);
+        let mut constants = Vec::with_capacity(0);
         let mut functions = Vec::with_capacity(0);
         while self.maybe(TokenType::RightBrace)?.is_none() {
+            let member_start = self.peek()?.slice.0;
+            let attributes = self.parse_attributes()?;
             match self.peek()?.r#type {
-                TokenType::KwPub => {
-                    self.expect_one()?;
-                    self.expect(TokenType::KwFun)?;
-                    functions.push(self.parse_function(true)?);
-                }
-                TokenType::KwFun => {
-                    self.expect_one()?;
-                    functions.push(self.parse_function(false)?);
+                TokenType::KwPub | TokenType::KwFun | TokenType::KwConst => {
+                    let visibility = self.parse_visibility()?;
+                    let tok = self.expect_one()?;
+                    match tok.r#type {
+                        TokenType::KwFun => {
+                            functions.push(self.in_context(ContextKind::Function, |this| {
+                                this.parse_function(member_start, visibility, attributes)
+                            })?)
+                        }
+                        TokenType::KwConst => {
+                            constants.push(self.in_context(ContextKind::Constant, |this| {
+                                this.parse_const(member_start, visibility, attributes, false)
+                            })?)
+                        }
+                        _ => {
+                            return Err(
+                                self.unexpected(tok, &[TokenType::KwFun, TokenType::KwConst])
+                            )
+                        }
+                    }
                 }
-                _ => variants.push(self.parse_enum_variant()?),
+                _ => variants.push(self.in_context(ContextKind::EnumVariant, |this| {
+                    this.parse_enum_variant(member_start, attributes)
+                })?),
             }
         }
-        self.ast.types.push(HirTypeDecl::Enum {
+        Ok(HirTypeDecl::Enum {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
             variants,
+            constants,
             functions,
-        });
+        })
+    }
+
+    fn parse_root_type(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirTypeDecl> {
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
+        // todo: generics
+        self.expect(TokenType::Equal)?;
+        let target = self.parse_type()?;
+        self.expect(TokenType::Semicolon)?;
+        Ok(HirTypeDecl::Alias {
+            span: self.span_from(start),
+            name,
+            visibility,
+            attributes,
+            target,
+        })
+    }
+
+    /// Parses `extern "abi" fun ...` or `extern "abi" { ... }` after the `extern` keyword.
+    fn parse_root_extern(
+        &mut self,
+        start: u32,
+        keyword: Token,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+        functions: &mut Vec<HirFunction>,
+    ) -> Result<()> {
+        let abi = self
+            .maybe(TokenType::String)?
+            .map(|tok| Lexer::string_contents(tok.slice));
+        let span = self.span_from(keyword.slice.0);
+        let tok = self.expect_one()?;
+        match tok.r#type {
+            TokenType::KwFun => {
+                let function = self.in_context(ContextKind::Function, |this| {
+                    this.parse_function(start, visibility, attributes)
+                })?;
+                functions.push(self.make_extern(function, span, abi)?);
+            }
+            TokenType::LeftBrace if visibility != HirVisibility::Private => {
+                return Err(Error::VisibilityNotAllowed(tok.slice));
+            }
+            TokenType::LeftBrace => {
+                while self.maybe(TokenType::RightBrace)?.is_none() {
+                    let member_start = self.peek()?.slice.0;
+                    let mut member_attributes = attributes.clone();
+                    member_attributes.extend(self.parse_attributes()?);
+                    let visibility = self.parse_visibility()?;
+                    self.expect(TokenType::KwFun)?;
+                    let function = self.in_context(ContextKind::Function, |this| {
+                        this.parse_function(member_start, visibility, member_attributes)
+                    })?;
+                    functions.push(self.make_extern(function, span, abi)?);
+                }
+            }
+            _ => {
+                return Err(self.unexpected(tok, &[TokenType::KwFun, TokenType::LeftBrace]));
+            }
+        }
         Ok(())
     }
 
-    fn parse_root_impl(&mut self) -> Result<()> {
-        let target = self.parse_type(0)?;
-        self.expect(TokenType::Colon)?;
-        let r#trait = self.parse_type(0)?;
+    /// Marks `function` as foreign, its `@link_name("...")` attribute becomes the link name.
+    fn make_extern(
+        &self,
+        mut function: HirFunction,
+        span: Str,
+        abi: Option<Str>,
+    ) -> Result<HirFunction> {
+        let mut link_name = None;
+        if let Some(index) = function
+            .attributes
+            .iter()
+            .position(|attribute| self.lex.slice(attribute.name) == "link_name")
+        {
+            let attribute = function.attributes.remove(index);
+            match attribute.args.as_slice() {
+                [HirAttributeArg {
+                    name: None,
+                    value:
+                        HirExpression {
+                            kind: HirExpressionKind::String { slice },
+                            ..
+                        },
+                    ..
+                }] => link_name = Some(Lexer::string_contents(*slice)),
+                _ => return Err(Error::InvalidAttribute(attribute.span)),
+            }
+        }
+        function.r#extern = Some(HirExtern {
+            span,
+            abi,
+            link_name,
+        });
+        Ok(function)
+    }
+
+    fn parse_root_impl(&mut self, start: u32, attributes: Vec<HirAttribute>) -> Result<HirImpl> {
+        let target = self.parse_type()?;
+        let r#trait = if self.maybe(TokenType::Colon)?.is_some() {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
         self.expect(TokenType::LeftBrace)?;
+        let mut types = Vec::with_capacity(0);
+        let mut constants = Vec::with_capacity(0);
         let mut functions = Vec::with_capacity(0);
-        while self.maybe(TokenType::RightBrace)?.is_none() {
-            if self.maybe(TokenType::KwPub)?.is_some() {
-                self.expect(TokenType::KwFun)?;
-                functions.push(self.parse_function(true)?);
+        self.in_associated_scope(|this| {
+            while this.maybe(TokenType::RightBrace)?.is_none() {
+                let member_start = this.peek()?.slice.0;
+                let attributes = this.parse_attributes()?;
+                if this.maybe(TokenType::KwType)?.is_some() {
+                    types.push(this.in_context(ContextKind::AssociatedType, |this| {
+                        this.parse_associated_type(member_start, attributes, false)
+                    })?);
+                    continue;
+                }
+                let visibility = this.parse_visibility()?;
+                if this.maybe(TokenType::KwConst)?.is_some() {
+                    constants.push(this.in_context(ContextKind::Constant, |this| {
+                        this.parse_const(member_start, visibility, attributes, false)
+                    })?);
+                    continue;
+                }
+                this.expect(TokenType::KwFun)?;
+                functions.push(this.in_context(ContextKind::Function, |this| {
+                    this.parse_function(member_start, visibility, attributes)
+                })?);
             }
-            self.expect(TokenType::KwFun)?;
-            functions.push(self.parse_function(false)?);
-        }
-        self.ast.impls.push(HirImpl {
+            Ok(())
+        })?;
+        Ok(HirImpl {
+            span: self.span_from(start),
+            attributes,
             target,
             r#trait,
+            types,
+            constants,
             functions,
-        });
-        Ok(())
+        })
     }
 
-    fn parse_root_function(&mut self, public: bool) -> Result<()> {
-        let function = self.parse_function(public)?;
-        self.ast.functions.push(function);
-        Ok(())
+    /// Parses an optional `pub`, `pub(module)` or `pub(package)`.
+    fn parse_visibility(&mut self) -> Result<HirVisibility> {
+        if self.maybe(TokenType::KwPub)?.is_none() {
+            return Ok(HirVisibility::Private);
+        }
+        self.parse_visibility_scope()
+    }
+
+    /// Parses the optional scope after an already consumed `pub`.
+    fn parse_visibility_scope(&mut self) -> Result<HirVisibility> {
+        if self.maybe(TokenType::LeftParen)?.is_none() {
+            return Ok(HirVisibility::Public);
+        }
+        let scope = self.expect_one()?;
+        let visibility = match scope.r#type {
+            TokenType::KwModule => HirVisibility::Module,
+            TokenType::Identifier if self.lex.slice(scope.slice) == "package" => {
+                HirVisibility::Package
+            }
+            _ => {
+                self.expecting_one(Expected::Token(TokenType::KwModule));
+                self.expecting_one(Expected::Contextual("package"));
+                return Err(self.unexpected(scope, &[]));
+            }
+        };
+        self.expect(TokenType::RightParen)?;
+        Ok(visibility)
+    }
+
+    fn parse_attributes(&mut self) -> Result<Vec<HirAttribute>> {
+        let mut attributes = Vec::with_capacity(0);
+        loop {
+            let tok = self.peek()?;
+            if !matches!(tok.r#type, TokenType::At | TokenType::Hashtag) {
+                break;
+            }
+            self.expect_one()?;
+            self.parse_attribute(tok, &mut attributes)?;
+        }
+        Ok(attributes)
+    }
+
+    /// Parses the attribute(s) following an already consumed `@` or `#` token.
+    fn parse_attribute(&mut self, start: Token, buf: &mut Vec<HirAttribute>) -> Result<()> {
+        self.in_context(ContextKind::Attribute, |this| {
+            if start.r#type == TokenType::At {
+                buf.push(this.parse_attribute_body(start.slice.0)?);
+                return Ok(());
+            }
+            this.expect(TokenType::LeftBracket)?;
+            loop {
+                let start = this.peek()?.slice.0;
+                buf.push(this.parse_attribute_body(start)?);
+                let next = this.expect_one()?;
+                match next.r#type {
+                    TokenType::RightBracket => break,
+                    TokenType::Comma => {}
+                    _ => {
+                        return Err(
+                            this.unexpected(next, &[TokenType::RightBracket, TokenType::Comma])
+                        )
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn parse_attribute_body(&mut self, start: u32) -> Result<HirAttribute> {
+        let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
+        let mut args = Vec::with_capacity(0);
+        if self.maybe(TokenType::LeftParen)?.is_some() {
+            while self.peek()?.r#type != TokenType::RightParen {
+                args.push(self.parse_attribute_arg()?);
+                if self.maybe(TokenType::Comma)?.is_none() {
+                    break;
+                }
+            }
+            self.expect(TokenType::RightParen)?;
+        }
+        Ok(HirAttribute {
+            span: self.span_from(start),
+            name,
+            args,
+        })
+    }
+
+    /// Parses `value` or `name = value`.
+    fn parse_attribute_arg(&mut self) -> Result<HirAttributeArg> {
+        let start = self.peek()?.slice.0;
+        let named = self.peek()?.r#type == TokenType::Identifier
+            && matches!(self.peek_ahead(1)?, Some(next) if next.r#type == TokenType::Equal);
+        let name = if named {
+            let name = self.expect_one()?.slice;
+            self.expect_one()?;
+            Some(name)
+        } else {
+            None
+        };
+        let value = self.parse_expression()?;
+        Ok(HirAttributeArg {
+            span: self.span_from(start),
+            name,
+            value,
+        })
     }
 
-    fn parse_struct_field(&mut self, public: bool) -> Result<HirStructField> {
-        let r#type = self.parse_type(0)?;
+    fn parse_struct_field(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirStructField> {
+        let r#type = self.parse_type()?;
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         self.expect(TokenType::Semicolon)?;
         Ok(HirStructField {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
             r#type,
         })
     }
 
-    fn parse_enum_variant(&mut self) -> Result<HirEnumVariant> {
+    fn parse_enum_variant(
+        &mut self,
+        start: u32,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirEnumVariant> {
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         match self.peek()?.r#type {
             TokenType::Semicolon => {
                 self.expect_one()?;
-                Ok(HirEnumVariant::Empty { name })
+                Ok(HirEnumVariant::Empty {
+                    span: self.span_from(start),
+                    name,
+                    attributes,
+                })
             }
             TokenType::LeftParen => {
                 self.expect_one()?;
                 let mut types = Vec::with_capacity(0);
                 while self.maybe(TokenType::RightParen)?.is_none() {
-                    let r#type = self.parse_type(0)?;
+                    let r#type = self.parse_type()?;
                     types.push(r#type);
                     if self.maybe(TokenType::Comma)?.is_none() {
                         self.expect(TokenType::RightParen)?;
@@ -283,26 +1027,60 @@ impl Parser {
                     }
                 }
                 self.expect(TokenType::Semicolon)?;
-                Ok(HirEnumVariant::Tuple { name, types })
+                Ok(HirEnumVariant::Tuple {
+                    span: self.span_from(start),
+                    name,
+                    attributes,
+                    types,
+                })
             }
             TokenType::LeftBrace => {
                 self.expect_one()?;
                 let mut fields = Vec::with_capacity(0);
                 while self.maybe(TokenType::RightBrace)?.is_none() {
-                    fields.push(self.parse_struct_field(true)?);
+                    let field_start = self.peek()?.slice.0;
+                    let field_attributes = self.parse_attributes()?;
+                    fields.push(self.in_context(ContextKind::StructField, |this| {
+                        this.parse_struct_field(
+                            field_start,
+                            HirVisibility::Public,
+                            field_attributes,
+                        )
+                    })?);
                 }
-                Ok(HirEnumVariant::Struct { name, fields })
+                Ok(HirEnumVariant::Struct {
+                    span: self.span_from(start),
+                    name,
+                    attributes,
+                    fields,
+                })
+            }
+            _ => {
+                let tok = self.peek()?;
+                Err(self.unexpected(
+                    tok,
+                    &[
+                        TokenType::Semicolon,
+                        TokenType::LeftParen,
+                        TokenType::LeftBrace,
+                    ],
+                ))
             }
-            _ => Err(Error::UnexpectedToken(self.expect_one()?.slice)),
         }
     }
 
-    fn parse_function(&mut self, public: bool) -> Result<HirFunction> {
+    fn parse_function(
+        &mut self,
+        start: u32,
+        visibility: HirVisibility,
+        attributes: Vec<HirAttribute>,
+    ) -> Result<HirFunction> {
         let name = self.expect(TokenType::Identifier)?.slice;
+        self.name_context(name);
         // todo: generics
-        let params = self.parse_function_params()?;
+        let (receiver, params) = self.parse_function_params()?;
         let return_type = if self.maybe(TokenType::Arrow)?.is_some() {
-            Some(self.parse_type(0)?)
+            Some(self.parse_type()?)
         } else {
             None
         };
@@ -312,177 +1090,558 @@ impl Parser {
             Some(self.parse_block()?)
         };
         Ok(HirFunction {
+            span: self.span_from(start),
             name,
-            public,
+            visibility,
+            attributes,
+            receiver,
             params,
             return_type,
             body,
+            r#extern: None,
         })
     }
 
-    fn parse_type(&mut self, depth: usize) -> Result<HirType> {
-        if self.maybe(TokenType::Star)?.is_some() {
-            if depth == 0 && self.maybe(TokenType::KwSelf)?.is_some() {
-                return Ok(HirType::Reference {
-                    r#type: Box::new(HirType::SelfType),
-                });
+    fn parse_type(&mut self) -> Result<HirType> {
+        let r#type = self.parse_type_inner()?;
+        self.parse_type_suffix(r#type, true)
+    }
+
+    /// Parses the `?` suffixes of a type, `*int?` is an optional reference. `??` lexes as a
+    /// single token, it adds two suffixes if `coalesce_suffix` is set and ends the type otherwise,
+    /// e.g. in `a as int ?? 0`.
+    fn parse_type_suffix(&mut self, mut r#type: HirType, coalesce_suffix: bool) -> Result<HirType> {
+        let start = r#type.span.0;
+        loop {
+            self.expecting(&[TokenType::Question]);
+            if coalesce_suffix {
+                self.expecting(&[TokenType::QuestionQuestion]);
             }
-            if self.maybe(TokenType::KwConst)?.is_some() {
-                if depth == 0 && self.maybe(TokenType::KwSelf)?.is_some() {
-                    return Ok(HirType::ConstReference {
-                        r#type: Box::new(HirType::SelfType),
-                    });
-                }
-                let r#type = Box::new(self.parse_type(depth + 1)?);
-                return Ok(HirType::ConstReference { r#type });
+            let tok = self.peek()?;
+            // the end of each `?` relative to the token
+            let ends: &[u32] = match tok.r#type {
+                TokenType::Question => &[1],
+                TokenType::QuestionQuestion if coalesce_suffix => &[1, 2],
+                _ => return Ok(r#type),
+            };
+            self.expect_one()?;
+            for end in ends {
+                r#type = HirType {
+                    span: Str(start, tok.slice.0 - start + end),
+                    kind: HirTypeKind::Optional {
+                        r#type: Box::new(r#type),
+                    },
+                };
             }
-            let r#type = Box::new(self.parse_type(depth + 1)?);
-            return Ok(HirType::Reference { r#type });
         }
-        if depth == 0 && self.maybe(TokenType::KwSelf)?.is_some() {
-            return Ok(HirType::Reference {
-                r#type: Box::new(HirType::SelfType),
+    }
+
+    fn parse_type_inner(&mut self) -> Result<HirType> {
+        let start = self.peek()?.slice.0;
+        if self.maybe(TokenType::Star)?.is_some() {
+            let constant = self.maybe(TokenType::KwConst)?.is_some();
+            let r#type = Box::new(self.parse_type_inner()?);
+            let kind = if constant {
+                HirTypeKind::ConstReference { r#type }
+            } else {
+                HirTypeKind::Reference { r#type }
+            };
+            return Ok(HirType {
+                span: self.span_from(start),
+                kind,
             });
         }
-        let base = self.expect(TokenType::Identifier)?.slice;
-        let mut parts = vec![base];
-        while self.maybe(TokenType::Dot)?.is_some() {
-            parts.push(self.expect(TokenType::Identifier)?.slice);
+        if self.maybe(TokenType::KwSelf)?.is_some() {
+            let mut r#type = HirType {
+                span: self.span_from(start),
+                kind: HirTypeKind::SelfType,
+            };
+            while self.maybe(TokenType::Dot)?.is_some() {
+                let name = self.expect(TokenType::Identifier)?.slice;
+                r#type = HirType {
+                    span: self.span_from(start),
+                    kind: HirTypeKind::Projection {
+                        r#type: Box::new(r#type),
+                        name,
+                    },
+                };
+            }
+            return Ok(r#type);
+        }
+        let base = self.expect(TokenType::Identifier)?.slice;
+        let mut parts = vec![base];
+        while self.maybe(TokenType::Dot)?.is_some() {
+            parts.push(self.expect(TokenType::Identifier)?.slice);
+        }
+        let name = self.lex.slice(base);
+        if self
+            .associated_types
+            .iter()
+            .any(|associated| self.lex.slice(*associated) == name)
+        {
+            // `Item.Key` is `Self.Item.Key`, the implicit `Self` has an empty span
+            let mut r#type = HirType {
+                span: Str(start, 0),
+                kind: HirTypeKind::SelfType,
+            };
+            for name in parts {
+                r#type = HirType {
+                    span: Str(start, name.0 + name.1 - start),
+                    kind: HirTypeKind::Projection {
+                        r#type: Box::new(r#type),
+                        name,
+                    },
+                };
+            }
+            return Ok(r#type);
         }
         // todo: generics
-        Ok(HirType::Direct {
-            path: HirPath { parts },
+        Ok(HirType {
+            span: self.span_from(start),
+            kind: HirTypeKind::Direct {
+                path: HirPath::new(parts),
+            },
         })
     }
 
-    fn parse_import_group(&mut self, buf: &mut Vec<Str>) -> Result<()> {
+    fn parse_import_group(
+        &mut self,
+        buf: &mut Vec<Str>,
+        imports: &mut Vec<(HirPath, Option<Str>, bool)>,
+    ) -> Result<()> {
         self.expect(TokenType::LeftBrace)?;
         loop {
-            self.parse_root_import(buf)?;
+            self.parse_root_import(buf, buf.len(), imports)?;
             let next = self.expect_one()?;
             match next.r#type {
                 TokenType::RightBrace => break,
                 TokenType::Comma => {}
-                _ => return Err(Error::UnexpectedToken(next.slice)),
+                _ => return Err(self.unexpected(next, &[TokenType::RightBrace, TokenType::Comma])),
             }
         }
         Ok(())
     }
 
-    fn parse_function_params(&mut self) -> Result<Vec<HirFunctionParam>> {
+    fn parse_function_params(&mut self) -> Result<(Option<HirReceiver>, Vec<HirFunctionParam>)> {
         self.expect(TokenType::LeftParen)?;
+        let receiver = self.parse_receiver()?;
         let mut params = Vec::with_capacity(0);
+        if receiver.is_some() && self.maybe(TokenType::Comma)?.is_none() {
+            self.expect(TokenType::RightParen)?;
+            return Ok((receiver, params));
+        }
         while self.maybe(TokenType::RightParen)?.is_none() {
-            let r#type = self.parse_type(0)?;
-            let name = self.expect(TokenType::Identifier)?.slice;
-            params.push(HirFunctionParam { name, r#type });
+            let first = receiver.is_none() && params.is_empty();
+            params.push(self.in_context(ContextKind::Parameter, |this| {
+                let start = this.peek()?.slice.0;
+                let attributes = this.parse_attributes()?;
+                // the receiver is only written as `self`, `*self` or `*const self` in front
+                if this.parse_receiver()?.is_some() {
+                    if let (true, Some(last)) = (first, attributes.last()) {
+                        let end = last.span.0 + last.span.1;
+                        return Err(Error::AttributeOnReceiver(Str(start, end - start)));
+                    }
+                    return Err(Error::InvalidReceiver(this.span_from(start)));
+                }
+                let r#type = this.parse_type()?;
+                let name = this.expect(TokenType::Identifier)?.slice;
+                this.name_context(name);
+                if this.lex.slice(name) == "self" {
+                    return Err(Error::InvalidReceiver(this.span_from(start)));
+                }
+                Ok(HirFunctionParam {
+                    span: this.span_from(start),
+                    name,
+                    attributes,
+                    r#type,
+                })
+            })?);
             if self.maybe(TokenType::Comma)?.is_none() {
                 self.expect(TokenType::RightParen)?;
                 break;
             }
         }
-        Ok(params)
+        Ok((receiver, params))
+    }
+
+    /// Parses `self`, `*self` or `*const self` at the start of the parameter list.
+    fn parse_receiver(&mut self) -> Result<Option<HirReceiver>> {
+        let tokens = [Some(self.peek()?), self.peek_ahead(1)?, self.peek_ahead(2)?];
+        let types = tokens.map(|tok| tok.map(|tok| tok.r#type));
+        let (kind, len) = if self.is_self(tokens[0]) {
+            (HirReceiverKind::Value, 1)
+        } else if types[0] == Some(TokenType::Star) && self.is_self(tokens[1]) {
+            (HirReceiverKind::Reference, 2)
+        } else if types[0] == Some(TokenType::Star)
+            && types[1] == Some(TokenType::KwConst)
+            && self.is_self(tokens[2])
+        {
+            (HirReceiverKind::ConstReference, 3)
+        } else {
+            return Ok(None);
+        };
+        let start = self.peek()?.slice.0;
+        for _ in 0..len {
+            self.expect_one()?;
+        }
+        Ok(Some(HirReceiver {
+            span: self.span_from(start),
+            kind,
+        }))
     }
 
+    fn is_self(&self, tok: Option<Token>) -> bool {
+        matches!(tok, Some(tok) if tok.r#type == TokenType::Identifier
+            && self.lex.slice(tok.slice) == "self")
+    }
+
+    /// Parses a block, statements that fail to parse are reported and left out.
     fn parse_block(&mut self) -> Result<HirBlock> {
-        self.expect(TokenType::LeftBrace)?;
+        let start = self.expect(TokenType::LeftBrace)?.slice.0;
+        let depth = self.depth;
         let mut statements = Vec::with_capacity(0);
-        while self.maybe(TokenType::RightBrace)?.is_none() {
-            statements.push(self.parse_statement()?);
+        let mut tail = None;
+        loop {
+            match self.maybe(TokenType::RightBrace) {
+                Ok(Some(_)) => break,
+                Ok(None) => {}
+                Err(err @ Error::UnexpectedEof { .. }) => {
+                    self.report(err);
+                    break;
+                }
+                Err(err) => {
+                    self.report(err);
+                    continue;
+                }
+            }
+            let mark = self.last_end;
+            match self.parse_block_statement(&mut statements) {
+                Ok(None) => {}
+                Ok(Some(expr)) => {
+                    tail = Some(Box::new(expr));
+                    break;
+                }
+                Err(err) => self.recover(err, mark, depth, is_statement_start),
+            }
         }
-        Ok(HirBlock { statements })
+        Ok(new_block(self.span_from(start), statements, tail))
     }
 
-    fn parse_statement(&mut self) -> Result<HirStatement> {
+    /// Parses the next statement of a block into `statements`, returns the tail expression if
+    /// the block ends after it.
+    fn parse_block_statement(
+        &mut self,
+        statements: &mut Vec<HirStatement>,
+    ) -> Result<Option<HirExpression>> {
+        let start = self.peek()?.slice.0;
+        let attributes = self.parse_attributes()?;
+        let kind = if !attributes.is_empty()
+            || self.peek()?.r#type.is_statement_keyword()
+            || self.is_label()?
+        {
+            self.parse_statement()?
+        } else {
+            let expr = self.parse_statement_expression()?;
+            if self.maybe(TokenType::RightBrace)?.is_some() {
+                return Ok(Some(expr));
+            }
+            self.parse_expression_statement(expr)?
+        };
+        statements.push(HirStatement {
+            span: self.span_from(start),
+            attributes,
+            kind,
+        });
+        Ok(None)
+    }
+
+    fn parse_statement(&mut self) -> Result<HirStatementKind> {
         let tok = self.peek()?;
+        if let Some(label) = self.parse_label()? {
+            let kind = self.parse_loop(Some(label))?;
+            return self.end_block_statement(tok.slice.0, kind);
+        }
         match tok.r#type {
             TokenType::KwVar => {
                 self.expect_one()?;
-                let (name, r#type, expr) = self.parse_var_decl()?;
-                Ok(HirStatement::VarDecl { name, r#type, expr })
+                let name = self.expect(TokenType::Identifier)?.slice;
+                let (r#type, expr) = self.parse_var_decl()?;
+                Ok(HirStatementKind::VarDecl { name, r#type, expr })
             }
             TokenType::KwConst => {
                 self.expect_one()?;
-                let (name, r#type, expr) = self.parse_var_decl()?;
-                Ok(HirStatement::ConstDecl { name, r#type, expr })
+                let name = self.expect(TokenType::Identifier)?.slice;
+                let (r#type, expr) = self.parse_var_decl()?;
+                Ok(HirStatementKind::ConstDecl { name, r#type, expr })
             }
             TokenType::KwIf => {
                 self.expect_one()?;
                 let cond = self.parse_expression()?;
                 let block = self.parse_block()?;
                 let else_block = self.parse_else()?;
-                Ok(HirStatement::If {
+                let kind = HirStatementKind::If {
                     cond,
                     block,
                     else_block,
-                })
-            }
-            TokenType::KwWhile => {
-                self.expect_one()?;
-                let cond = self.parse_expression()?;
-                let block = self.parse_block()?;
-                Ok(HirStatement::While { cond, block })
+                };
+                self.end_block_statement(tok.slice.0, kind)
             }
-            TokenType::KwFor => {
-                self.expect_one()?;
-                let name = self.expect(TokenType::Identifier)?.slice;
-                self.expect(TokenType::KwIn)?;
-                let expr = self.parse_expression()?;
-                let block = self.parse_block()?;
-                Ok(HirStatement::For { name, expr, block })
+            TokenType::KwWhile | TokenType::KwFor | TokenType::KwLoop => {
+                let kind = self.parse_loop(None)?;
+                self.end_block_statement(tok.slice.0, kind)
             }
             TokenType::KwReturn => {
+                let tok = self.expect_one()?;
+                self.check_jump(tok, None)?;
+                let expr = if self.maybe(TokenType::Semicolon)?.is_some() {
+                    None
+                } else {
+                    let expr = self.parse_expression()?;
+                    self.expect(TokenType::Semicolon)?;
+                    Some(expr)
+                };
+                Ok(HirStatementKind::Return { expr })
+            }
+            TokenType::KwDefer => {
                 self.expect_one()?;
-                let expr = self.parse_expression()?;
-                self.expect(TokenType::Semicolon)?;
-                Ok(HirStatement::Return { expr })
+                let next = self.peek()?;
+                if matches!(
+                    next.r#type,
+                    TokenType::KwVar | TokenType::KwConst | TokenType::KwDefer
+                ) {
+                    return Err(self.unexpected(next, &[]));
+                }
+                let outer = self.defer_loops.replace(self.loops.len());
+                let kind = self.parse_deferred();
+                self.defer_loops = outer;
+                let kind = kind?;
+                let statement = HirStatement {
+                    span: self.span_from(next.slice.0),
+                    attributes: Vec::with_capacity(0),
+                    kind,
+                };
+                Ok(HirStatementKind::Defer {
+                    statement: Box::new(statement),
+                })
             }
             TokenType::KwContinue => {
-                self.expect_one()?;
+                let tok = self.expect_one()?;
+                let (label, _) = self.parse_jump_label(tok)?;
                 self.expect(TokenType::Semicolon)?;
-                Ok(HirStatement::Continue)
+                Ok(HirStatementKind::Continue { label })
             }
             TokenType::KwBreak => {
-                self.expect_one()?;
-                self.expect(TokenType::Semicolon)?;
-                Ok(HirStatement::Break)
+                let tok = self.expect_one()?;
+                let (label, target) = self.parse_jump_label(tok)?;
+                let value = if self.maybe(TokenType::Semicolon)?.is_some() {
+                    None
+                } else {
+                    let value = self.parse_expression()?;
+                    // only a `loop` has a value
+                    if self.loops[target].0 != TokenType::KwLoop {
+                        return Err(Error::InvalidBreakValue(value.span));
+                    }
+                    self.expect(TokenType::Semicolon)?;
+                    Some(value)
+                };
+                Ok(HirStatementKind::Break { label, value })
             }
             _ => {
+                let expr = self.parse_statement_expression()?;
+                self.parse_expression_statement(expr)
+            }
+        }
+    }
+
+    /// Parses the expression at the start of a statement or the body of a match arm, a block-like
+    /// expression ends after its closing brace.
+    fn parse_statement_expression(&mut self) -> Result<HirExpression> {
+        let tok = self.peek()?;
+        if matches!(
+            tok.r#type,
+            TokenType::LeftBrace
+                | TokenType::KwIf
+                | TokenType::KwLoop
+                | TokenType::KwMatch
+                | TokenType::Label
+        ) {
+            return self.parse_unary_expression(tok);
+        }
+        self.parse_expression()
+    }
+
+    /// Parses the rest of a statement starting with the expression `expr`.
+    fn parse_expression_statement(&mut self, expr: HirExpression) -> Result<HirStatementKind> {
+        if self.maybe(TokenType::Equal)?.is_some() {
+            if !matches!(
+                expr.kind,
+                HirExpressionKind::Access { .. }
+                    | HirExpressionKind::DotAccess { .. }
+                    | HirExpressionKind::IndexAccess { .. }
+                    | HirExpressionKind::Deref { .. }
+            ) {
+                return Err(Error::UnexpectedExpression(expr.span));
+            }
+            let value = self.parse_expression()?;
+            self.expect(TokenType::Semicolon)?;
+            return Ok(HirStatementKind::Assign { expr, value });
+        }
+        match expr {
+            HirExpression {
+                kind: HirExpressionKind::Call { expr, args },
+                ..
+            } => {
+                self.expect(TokenType::Semicolon)?;
+                Ok(HirStatementKind::Call { expr: *expr, args })
+            }
+            // block-like expressions do not require a semicolon
+            expr if is_block_like(&expr) => {
+                self.maybe(TokenType::Semicolon)?;
+                Ok(HirStatementKind::Expr { expr })
+            }
+            expr => {
+                self.expect(TokenType::Semicolon)?;
+                Ok(HirStatementKind::Expr { expr })
+            }
+        }
+    }
+
+    /// Consumes the optional `;` after the `if` or loop statement `kind` starting at `start`.
+    ///
+    /// An `if`-`else` or `loop` followed by `;` becomes an expression statement, so that it is not
+    /// turned into the value of the enclosing block.
+    fn end_block_statement(
+        &mut self,
+        start: u32,
+        kind: HirStatementKind,
+    ) -> Result<HirStatementKind> {
+        let span = self.span_from(start);
+        if self.maybe(TokenType::Semicolon)?.is_none() {
+            return Ok(kind);
+        }
+        let kind = match kind {
+            HirStatementKind::If {
+                cond,
+                block,
+                else_block: Some(else_block),
+            } => HirExpressionKind::If {
+                cond: Box::new(cond),
+                block,
+                else_block: Some(else_block),
+            },
+            HirStatementKind::Loop { label, block } => HirExpressionKind::Loop { label, block },
+            kind => return Ok(kind),
+        };
+        Ok(HirStatementKind::Expr {
+            expr: HirExpression { span, kind },
+        })
+    }
+
+    fn is_label(&mut self) -> Result<bool> {
+        Ok(self.peek()?.r#type == TokenType::Label)
+    }
+
+    /// Parses `'label:` in front of a loop, returns the name of the label without the `'`.
+    fn parse_label(&mut self) -> Result<Option<Str>> {
+        let Some(tok) = self.maybe(TokenType::Label)? else {
+            return Ok(None);
+        };
+        self.expect(TokenType::Colon)?;
+        Ok(Some(label_name(tok)))
+    }
+
+    /// Parses the `'label` after the `break` or `continue` token `jump`, it must name an enclosing
+    /// loop. Returns the label and the index of the targeted loop in `loops`.
+    fn parse_jump_label(&mut self, jump: Token) -> Result<(Option<Str>, usize)> {
+        let Some(tok) = self.maybe(TokenType::Label)? else {
+            // targets the innermost loop
+            let Some(target) = self.loops.len().checked_sub(1) else {
+                return Err(Error::JumpOutsideLoop(jump.slice));
+            };
+            self.check_jump(jump, Some(target))?;
+            return Ok((None, target));
+        };
+        let name = self.lex.slice(label_name(tok));
+        let Some(target) = self
+            .loops
+            .iter()
+            .rposition(|(_, label)| label.is_some_and(|label| self.lex.slice(label) == name))
+        else {
+            return Err(Error::UnknownLabel(tok.slice));
+        };
+        self.check_jump(jump, Some(target))?;
+        Ok((Some(label_name(tok)), target))
+    }
+
+    /// Rejects the jump `tok` if it leaves the innermost deferred statement, `target` is the index
+    /// of the loop it breaks or continues, `None` for `return` and `?`.
+    fn check_jump(&self, tok: Token, target: Option<usize>) -> Result<()> {
+        match self.defer_loops {
+            Some(loops) if target.is_none_or(|target| target < loops) => {
+                Err(Error::JumpOutOfDefer(tok.slice))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses the statement or block after `defer`.
+    fn parse_deferred(&mut self) -> Result<HirStatementKind> {
+        if self.peek()?.r#type != TokenType::LeftBrace {
+            return self.parse_statement();
+        }
+        let block = self.parse_block()?;
+        self.maybe(TokenType::Semicolon)?;
+        Ok(HirStatementKind::Expr {
+            expr: HirExpression {
+                span: block.span,
+                kind: HirExpressionKind::Block { block },
+            },
+        })
+    }
+
+    fn parse_loop(&mut self, label: Option<Str>) -> Result<HirStatementKind> {
+        let tok = self.expect_one()?;
+        match tok.r#type {
+            TokenType::KwWhile => {
+                let cond = self.parse_expression()?;
+                let block = self.parse_loop_block(tok.r#type, label)?;
+                Ok(HirStatementKind::While { label, cond, block })
+            }
+            TokenType::KwFor => {
+                let name = self.expect(TokenType::Identifier)?.slice;
+                self.expect(TokenType::KwIn)?;
                 let expr = self.parse_expression()?;
-                match expr {
-                    HirExpression::Call { expr, args } => {
-                        self.expect(TokenType::Semicolon)?;
-                        Ok(HirStatement::Call { expr: *expr, args })
-                    }
-                    HirExpression::Access { .. } => {
-                        self.expect(TokenType::Equal)?;
-                        let value = self.parse_expression()?;
-                        self.expect(TokenType::Semicolon)?;
-                        Ok(HirStatement::Assign { expr, value })
-                    }
-                    HirExpression::DotAccess { .. } => {
-                        self.expect(TokenType::Equal)?;
-                        let value = self.parse_expression()?;
-                        self.expect(TokenType::Semicolon)?;
-                        Ok(HirStatement::Assign { expr, value })
-                    }
-                    HirExpression::IndexAccess { .. } => {
-                        self.expect(TokenType::Equal)?;
-                        let value = self.parse_expression()?;
-                        self.expect(TokenType::Semicolon)?;
-                        Ok(HirStatement::Assign { expr, value })
-                    }
-                    // todo: add error slice
-                    _ => Err(Error::UnexpectedExpression),
-                }
+                let block = self.parse_loop_block(tok.r#type, label)?;
+                Ok(HirStatementKind::For {
+                    label,
+                    name,
+                    expr,
+                    block,
+                })
             }
+            TokenType::KwLoop => {
+                let block = self.parse_loop_block(tok.r#type, label)?;
+                Ok(HirStatementKind::Loop { label, block })
+            }
+            _ => Err(self.unexpected(
+                tok,
+                &[TokenType::KwWhile, TokenType::KwFor, TokenType::KwLoop],
+            )),
         }
     }
 
-    fn parse_var_decl(&mut self) -> Result<(Str, Option<HirType>, Option<HirExpression>)> {
-        let name = self.expect(TokenType::Identifier)?.slice;
+    /// Parses the block of the loop starting with the keyword `kind`.
+    fn parse_loop_block(&mut self, kind: TokenType, label: Option<Str>) -> Result<HirBlock> {
+        self.loops.push((kind, label));
+        let result = self.parse_block();
+        self.loops.pop();
+        result
+    }
+
+    /// Parses the optional type and value after the name of a variable.
+    fn parse_var_decl(&mut self) -> Result<(Option<HirType>, Option<HirExpression>)> {
         let r#type = if self.maybe(TokenType::Colon)?.is_some() {
-            Some(self.parse_type(0)?)
+            Some(self.parse_type()?)
         } else {
             None
         };
@@ -492,115 +1651,329 @@ impl Parser {
             None
         };
         self.expect(TokenType::Semicolon)?;
-        Ok((name, r#type, expr))
+        Ok((r#type, expr))
     }
 
     fn parse_expression(&mut self) -> Result<HirExpression> {
+        self.parse_binary_expression(0)
+    }
+
+    /// Parses an operand with its unary operators and casts.
+    fn parse_operand(&mut self) -> Result<HirExpression> {
         let next = self.peek()?;
-        let left = self.parse_unary_expression(next)?;
-        let op = self.peek()?;
-        if !op.r#type.is_binary_op() {
-            return Ok(left);
-        }
-        self.parse_binary_expression(left)
+        let operand = self.parse_unary_expression(next)?;
+        // `as` binds tighter than every binary operator
+        self.parse_cast_expression(operand)
     }
 
     fn parse_unary_expression(&mut self, left: Token) -> Result<HirExpression> {
+        let start = left.slice.0;
         if left.r#type.is_unary_op() {
             self.expect_one()?;
+            let mutable =
+                left.r#type == TokenType::And && self.maybe(TokenType::KwConst)?.is_none();
             let next = self.peek()?;
-            return Ok(apply_unary(left, self.parse_unary_expression(next)?));
+            let arg = Box::new(self.parse_unary_expression(next)?);
+            let kind = match left.r#type {
+                TokenType::And => HirExpressionKind::AddressOf { mutable, expr: arg },
+                TokenType::Star => HirExpressionKind::Deref { expr: arg },
+                _ => HirExpressionKind::UnaryOp { op: left, arg },
+            };
+            return Ok(self.new_expression(start, kind));
         }
         match left.r#type {
             TokenType::LeftParen => {
                 self.expect_one()?;
-                let expr = self.parse_expression()?;
+                let mut expr = self.parse_expression()?;
                 self.expect(TokenType::RightParen)?;
+                expr.span = self.span_from(start);
                 self.parse_access_expression(expr)
             }
             TokenType::Integer => {
                 self.expect_one()?;
-                Ok(HirExpression::Int { slice: left.slice })
+                Ok(self.new_expression(start, HirExpressionKind::Int { slice: left.slice }))
             }
             TokenType::Float => {
                 self.expect_one()?;
-                Ok(HirExpression::Float { slice: left.slice })
+                Ok(self.new_expression(start, HirExpressionKind::Float { slice: left.slice }))
             }
             TokenType::String => {
                 self.expect_one()?;
-                self.parse_access_expression(HirExpression::String { slice: left.slice })
+                let expr =
+                    self.new_expression(start, HirExpressionKind::String { slice: left.slice });
+                self.parse_access_expression(expr)
+            }
+            TokenType::LeftBrace => {
+                let block = self.parse_block()?;
+                Ok(self.new_expression(start, HirExpressionKind::Block { block }))
+            }
+            TokenType::KwIf => {
+                self.expect_one()?;
+                let cond = self.parse_expression()?;
+                let block = self.parse_block()?;
+                let else_block = self.parse_else()?;
+                let kind = HirExpressionKind::If {
+                    cond: Box::new(cond),
+                    block,
+                    else_block,
+                };
+                Ok(self.new_expression(start, kind))
+            }
+            TokenType::KwLoop => {
+                self.expect_one()?;
+                let block = self.parse_loop_block(TokenType::KwLoop, None)?;
+                let kind = HirExpressionKind::Loop { label: None, block };
+                Ok(self.new_expression(start, kind))
+            }
+            TokenType::KwMatch => {
+                self.expect_one()?;
+                self.parse_match(start)
+            }
+            TokenType::Label => {
+                let label = self.parse_label()?;
+                self.expect(TokenType::KwLoop)?;
+                let block = self.parse_loop_block(TokenType::KwLoop, label)?;
+                let kind = HirExpressionKind::Loop { label, block };
+                Ok(self.new_expression(start, kind))
             }
             TokenType::Identifier => {
                 self.expect_one()?;
-                let mut left = HirExpression::Access { name: left.slice };
-                if self.peek()?.r#type == TokenType::LeftParen {
-                    left = HirExpression::Call {
-                        expr: Box::new(left),
-                        args: self.parse_function_call_args()?,
-                    };
-                }
+                let left =
+                    self.new_expression(start, HirExpressionKind::Access { name: left.slice });
+                let left = self.parse_call(left)?;
                 self.parse_access_expression(left)
             }
-            _ => Err(Error::InvalidUnaryExpression(left.slice)),
+            _ => {
+                self.expecting_one(Expected::Expression);
+                Err(self.unexpected(left, &[]))
+            }
         }
     }
 
-    fn parse_binary_expression(&mut self, left: HirExpression) -> Result<HirExpression> {
-        let op = self.expect_one()?;
-        let next = self.peek()?;
-        let right = self.parse_unary_expression(next)?;
-        let next = self.peek()?;
-        if !next.r#type.is_binary_op() {
-            return Ok(apply_binary(op, left, right));
+    /// Parses operands joined by binary operators with a precedence above `min_precedence`.
+    ///
+    /// Operators of the same precedence group according to their [`Associativity`], chaining
+    /// non-associative operators like `a < b < c` is an error.
+    fn parse_binary_expression(&mut self, min_precedence: usize) -> Result<HirExpression> {
+        let mut left = self.parse_operand()?;
+        let mut non_associative = None;
+        loop {
+            let op = self.peek()?;
+            self.expecting_one(Expected::Operator);
+            if !op.r#type.is_binary_op() || op.r#type.precedence() <= min_precedence {
+                return Ok(left);
+            }
+            let precedence = op.r#type.precedence();
+            if non_associative == Some(precedence) {
+                return Err(Error::NonAssociativeOperator(op.slice));
+            }
+            self.expect_one()?;
+            let right = match op.r#type.associativity() {
+                Associativity::Left => self.parse_binary_expression(precedence)?,
+                Associativity::Right => self.parse_binary_expression(precedence - 1)?,
+                Associativity::None => {
+                    non_associative = Some(precedence);
+                    self.parse_binary_expression(precedence)?
+                }
+            };
+            left = apply_binary(op, left, right);
         }
-        if op.r#type.precedence() < next.r#type.precedence() {
-            return Ok(apply_binary(op, left, self.parse_binary_expression(right)?));
+    }
+
+    fn parse_cast_expression(&mut self, mut left: HirExpression) -> Result<HirExpression> {
+        let start = left.span.0;
+        while self.maybe(TokenType::KwAs)?.is_some() {
+            let r#type = self.parse_type_inner()?;
+            let r#type = self.parse_type_suffix(r#type, false)?;
+            let kind = HirExpressionKind::Cast {
+                expr: Box::new(left),
+                r#type,
+            };
+            left = self.new_expression(start, kind);
         }
-        self.parse_binary_expression(apply_binary(op, left, right))
+        Ok(left)
     }
 
     fn parse_access_expression(&mut self, left: HirExpression) -> Result<HirExpression> {
-        match self.peek()?.r#type {
+        let start = left.span.0;
+        self.expecting(&[
+            TokenType::Dot,
+            TokenType::QuestionDot,
+            TokenType::Question,
+            TokenType::LeftBracket,
+        ]);
+        let kind = match self.peek()?.r#type {
             TokenType::Dot => {
                 self.expect_one()?;
                 let name = self.expect(TokenType::Identifier)?.slice;
-                let right = if self.peek()?.r#type == TokenType::LeftParen {
-                    HirExpression::Call {
-                        expr: Box::new(HirExpression::DotAccess {
-                            expr: Box::new(left),
-                            name,
-                        }),
-                        args: self.parse_function_call_args()?,
-                    }
-                } else {
-                    HirExpression::DotAccess {
-                        expr: Box::new(left),
-                        name,
-                    }
-                };
-                self.parse_access_expression(right)
+                HirExpressionKind::DotAccess {
+                    expr: Box::new(left),
+                    name,
+                }
+            }
+            TokenType::Question => {
+                let tok = self.expect_one()?;
+                self.check_jump(tok, None)?;
+                HirExpressionKind::Try {
+                    expr: Box::new(left),
+                }
+            }
+            TokenType::QuestionDot => {
+                self.expect_one()?;
+                let name = self.expect(TokenType::Identifier)?.slice;
+                HirExpressionKind::SafeDotAccess {
+                    expr: Box::new(left),
+                    name,
+                }
             }
             TokenType::LeftBracket => {
                 self.expect_one()?;
                 let index = self.parse_expression()?;
                 self.expect(TokenType::RightBracket)?;
-                let right = if self.peek()?.r#type == TokenType::LeftParen {
-                    HirExpression::Call {
-                        expr: Box::new(HirExpression::IndexAccess {
-                            expr: Box::new(left),
-                            index: Box::new(index),
-                        }),
-                        args: self.parse_function_call_args()?,
-                    }
-                } else {
-                    HirExpression::IndexAccess {
-                        expr: Box::new(left),
-                        index: Box::new(index),
+                HirExpressionKind::IndexAccess {
+                    expr: Box::new(left),
+                    index: Box::new(index),
+                }
+            }
+            _ => return Ok(left),
+        };
+        let right = self.new_expression(start, kind);
+        let right = if matches!(right.kind, HirExpressionKind::Try { .. }) {
+            right
+        } else {
+            self.parse_call(right)?
+        };
+        self.parse_access_expression(right)
+    }
+
+    /// Parses the arguments if `callee` is called.
+    fn parse_call(&mut self, callee: HirExpression) -> Result<HirExpression> {
+        self.expecting(&[TokenType::LeftParen]);
+        if self.peek()?.r#type != TokenType::LeftParen {
+            return Ok(callee);
+        }
+        let start = callee.span.0;
+        let kind = HirExpressionKind::Call {
+            expr: Box::new(callee),
+            args: self.parse_function_call_args()?,
+        };
+        Ok(self.new_expression(start, kind))
+    }
+
+    /// Parses the rest of a `match` expression after the keyword.
+    fn parse_match(&mut self, start: u32) -> Result<HirExpression> {
+        let expr = self.parse_expression()?;
+        self.expect(TokenType::LeftBrace)?;
+        let mut arms = Vec::with_capacity(0);
+        while self.maybe(TokenType::RightBrace)?.is_none() {
+            let arm_start = self.peek()?.slice.0;
+            let pattern = self.parse_pattern()?;
+            self.expect(TokenType::FatArrow)?;
+            let body = self.parse_statement_expression()?;
+            // block-like bodies do not require a comma
+            let block_like = is_block_like(&body);
+            arms.push(HirMatchArm {
+                span: self.span_from(arm_start),
+                pattern,
+                body,
+            });
+            if self.maybe(TokenType::Comma)?.is_none() && !block_like {
+                self.expect(TokenType::RightBrace)?;
+                break;
+            }
+        }
+        let kind = HirExpressionKind::Match {
+            expr: Box::new(expr),
+            arms,
+        };
+        Ok(self.new_expression(start, kind))
+    }
+
+    fn parse_pattern(&mut self) -> Result<HirPattern> {
+        let tok = self.peek()?;
+        let start = tok.slice.0;
+        let kind = match tok.r#type {
+            TokenType::Integer | TokenType::Float | TokenType::String => HirPatternKind::Literal {
+                value: self.parse_literal()?,
+            },
+            TokenType::Minus => {
+                let op = self.expect_one()?;
+                let tok = self.peek()?;
+                if !matches!(tok.r#type, TokenType::Integer | TokenType::Float) {
+                    return Err(self.unexpected(tok, &[TokenType::Integer, TokenType::Float]));
+                }
+                let arg = Box::new(self.parse_literal()?);
+                HirPatternKind::Literal {
+                    value: self.new_expression(start, HirExpressionKind::UnaryOp { op, arg }),
+                }
+            }
+            TokenType::Identifier => {
+                self.expect_one()?;
+                let mut parts = vec![tok.slice];
+                while self.maybe(TokenType::Dot)?.is_some() {
+                    parts.push(self.expect(TokenType::Identifier)?.slice);
+                }
+                let mut fields = Vec::with_capacity(0);
+                let tuple = self.maybe(TokenType::LeftParen)?.is_some();
+                if tuple {
+                    while self.maybe(TokenType::RightParen)?.is_none() {
+                        fields.push(self.parse_pattern()?);
+                        if self.maybe(TokenType::Comma)?.is_none() {
+                            self.expect(TokenType::RightParen)?;
+                            break;
+                        }
                     }
-                };
-                self.parse_access_expression(right)
+                }
+                match parts.as_slice() {
+                    [name] if !tuple && self.lex.slice(*name) == "_" => HirPatternKind::Wildcard,
+                    [name] if !tuple => HirPatternKind::Binding { name: *name },
+                    _ => HirPatternKind::Variant {
+                        path: HirPath::new(parts),
+                        fields,
+                    },
+                }
+            }
+            _ => {
+                return Err(self.unexpected(
+                    tok,
+                    &[
+                        TokenType::Identifier,
+                        TokenType::Integer,
+                        TokenType::Float,
+                        TokenType::String,
+                        TokenType::Minus,
+                    ],
+                ))
+            }
+        };
+        Ok(HirPattern {
+            span: self.span_from(start),
+            kind,
+        })
+    }
+
+    /// Parses an integer, float or string literal.
+    fn parse_literal(&mut self) -> Result<HirExpression> {
+        let tok = self.peek()?;
+        let kind = match tok.r#type {
+            TokenType::Integer => HirExpressionKind::Int { slice: tok.slice },
+            TokenType::Float => HirExpressionKind::Float { slice: tok.slice },
+            TokenType::String => HirExpressionKind::String { slice: tok.slice },
+            _ => {
+                return Err(self.unexpected(
+                    tok,
+                    &[TokenType::Integer, TokenType::Float, TokenType::String],
+                ))
             }
-            _ => Ok(left),
+        };
+        self.expect_one()?;
+        Ok(self.new_expression(tok.slice.0, kind))
+    }
+
+    fn new_expression(&self, start: u32, kind: HirExpressionKind) -> HirExpression {
+        HirExpression {
+            span: self.span_from(start),
+            kind,
         }
     }
 
@@ -623,17 +1996,22 @@ impl Parser {
     fn parse_else(&mut self) -> Result<Option<HirBlock>> {
         match self.peek()?.r#type {
             TokenType::KwElseif => {
-                self.expect_one()?;
+                let start = self.expect_one()?.slice.0;
                 let cond = self.parse_expression()?;
                 let block = self.parse_block()?;
                 let else_block = self.parse_else()?;
-                Ok(Some(HirBlock {
-                    statements: vec![HirStatement::If {
+                // `elseif` is `else { if ... }`
+                let span = self.span_from(start);
+                let statement = HirStatement {
+                    span,
+                    attributes: Vec::with_capacity(0),
+                    kind: HirStatementKind::If {
                         cond,
                         block,
                         else_block,
-                    }],
-                }))
+                    },
+                };
+                Ok(Some(new_block(span, vec![statement], None)))
             }
             TokenType::KwElse => {
                 self.expect_one()?;
@@ -644,16 +2022,1498 @@ impl Parser {
     }
 }
 
-fn apply_unary(op: Token, arg: HirExpression) -> HirExpression {
-    HirExpression::UnaryOp {
-        op,
-        arg: Box::new(arg),
+/// Whether `token_type` starts a module item, error recovery resumes there.
+fn is_item_start(token_type: TokenType) -> bool {
+    matches!(token_type, TokenType::At | TokenType::Hashtag) || ITEM_KEYWORDS.contains(&token_type)
+}
+
+/// Keywords that start a module item
+const ITEM_KEYWORDS: &[TokenType] = &[
+    TokenType::KwPub,
+    TokenType::KwModule,
+    TokenType::KwImport,
+    TokenType::KwConst,
+    TokenType::KwVar,
+    TokenType::KwTrait,
+    TokenType::KwType,
+    TokenType::KwStruct,
+    TokenType::KwEnum,
+    TokenType::KwImpl,
+    TokenType::KwFun,
+    TokenType::KwExtern,
+];
+
+/// Whether `token_type` starts a statement, error recovery resumes there.
+fn is_statement_start(token_type: TokenType) -> bool {
+    token_type.is_statement_keyword()
+        || matches!(
+            token_type,
+            TokenType::At | TokenType::Hashtag | TokenType::Label | TokenType::KwMatch
+        )
+}
+
+/// Whether `expr` ends with a block, it does not need a `;` as a statement.
+fn is_block_like(expr: &HirExpression) -> bool {
+    matches!(
+        expr.kind,
+        HirExpressionKind::Block { .. }
+            | HirExpressionKind::If { .. }
+            | HirExpressionKind::Loop { .. }
+            | HirExpressionKind::Match { .. }
+    )
+}
+
+/// The name of the label token `tok` without its `'`.
+fn label_name(tok: Token) -> Str {
+    Str(tok.slice.0 + 1, tok.slice.1 - 1)
+}
+
+/// Creates a block, a trailing `if`-`else` or `loop` statement becomes the tail expression.
+fn new_block(
+    span: Str,
+    mut statements: Vec<HirStatement>,
+    tail: Option<Box<HirExpression>>,
+) -> HirBlock {
+    if tail.is_some() {
+        return HirBlock {
+            span,
+            statements,
+            tail,
+        };
+    }
+    // statements with attributes stay statements, they may be removed by `@cfg`
+    let tail = match statements.pop() {
+        Some(HirStatement {
+            span,
+            attributes,
+            kind:
+                HirStatementKind::If {
+                    cond,
+                    block,
+                    else_block: Some(else_block),
+                },
+        }) if attributes.is_empty() => Some(Box::new(HirExpression {
+            span,
+            kind: HirExpressionKind::If {
+                cond: Box::new(cond),
+                block,
+                else_block: Some(else_block),
+            },
+        })),
+        Some(HirStatement {
+            span,
+            attributes,
+            kind: HirStatementKind::Loop { label, block },
+        }) if attributes.is_empty() => Some(Box::new(HirExpression {
+            span,
+            kind: HirExpressionKind::Loop { label, block },
+        })),
+        Some(statement) => {
+            statements.push(statement);
+            None
+        }
+        None => None,
+    };
+    HirBlock {
+        span,
+        statements,
+        tail,
     }
 }
 
 fn apply_binary(op: Token, lhs: HirExpression, rhs: HirExpression) -> HirExpression {
-    HirExpression::BinaryOp {
-        op,
-        args: Box::new([lhs, rhs]),
+    let span = Str(lhs.span.0, rhs.span.0 + rhs.span.1 - lhs.span.0);
+    let args = Box::new([lhs, rhs]);
+    let kind = if op.r#type == TokenType::QuestionQuestion {
+        HirExpressionKind::Coalesce { args }
+    } else {
+        HirExpressionKind::BinaryOp { op, args }
+    };
+    HirExpression { span, kind }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::hir::{
+        HirBlock, HirExpression, HirExpressionKind, HirFunction, HirPattern, HirPatternKind,
+        HirReceiverKind, HirStatementKind, HirTypeDecl, HirTypeKind, HirVisibility,
+    };
+    use crate::prelude::*;
+
+    use super::{Expected, Parser};
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Assoc {
+        Left,
+        Right,
+        None,
+    }
+
+    /// Binary operators from the loosest to the tightest binding level
+    const LEVELS: &[(&[&str], Assoc)] = &[
+        (&["or"], Assoc::Left),
+        (&["and"], Assoc::Left),
+        (&["|"], Assoc::Left),
+        (&["^"], Assoc::Left),
+        (&["&"], Assoc::Left),
+        (&["==", "!="], Assoc::None),
+        (&["<", ">", "<=", ">="], Assoc::None),
+        (&["??"], Assoc::Right),
+        (&["<<", ">>"], Assoc::Left),
+        (&["+", "-"], Assoc::Left),
+        (&["*", "/", "%"], Assoc::Left),
+    ];
+
+    const UNARY: &[&str] = &["-", "+", "!", "*", "&", "&const "];
+
+    fn sexpr(parser: &Parser, expr: &HirExpression) -> String {
+        let slice = |slice| parser.lex.slice(slice);
+        match &expr.kind {
+            HirExpressionKind::Access { name } => slice(*name).to_string(),
+            HirExpressionKind::Int { slice: value } => slice(*value).to_string(),
+            HirExpressionKind::BinaryOp { op, args } => format!(
+                "({} {} {})",
+                sexpr(parser, &args[0]),
+                slice(op.slice),
+                sexpr(parser, &args[1])
+            ),
+            HirExpressionKind::Coalesce { args } => format!(
+                "({} ?? {})",
+                sexpr(parser, &args[0]),
+                sexpr(parser, &args[1])
+            ),
+            HirExpressionKind::UnaryOp { op, arg } => {
+                format!("({}{})", slice(op.slice), sexpr(parser, arg))
+            }
+            HirExpressionKind::Deref { expr } => format!("(*{})", sexpr(parser, expr)),
+            HirExpressionKind::AddressOf { mutable, expr } => {
+                let prefix = if *mutable { "&" } else { "&const " };
+                format!("({prefix}{})", sexpr(parser, expr))
+            }
+            HirExpressionKind::Cast { expr, r#type } => {
+                let HirTypeKind::Direct { path } = &r#type.kind else {
+                    panic!("unexpected type {type:?}");
+                };
+                format!("({} as {})", sexpr(parser, expr), slice(path.parts[0]))
+            }
+            HirExpressionKind::Call { expr, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| sexpr(parser, arg))
+                    .collect::<Vec<_>>();
+                format!("{}({})", sexpr(parser, expr), args.join(", "))
+            }
+            HirExpressionKind::DotAccess { expr, name } => {
+                format!("{}.{}", sexpr(parser, expr), slice(*name))
+            }
+            HirExpressionKind::Try { expr } => format!("{}?", sexpr(parser, expr)),
+            HirExpressionKind::IndexAccess { expr, index } => {
+                format!("{}[{}]", sexpr(parser, expr), sexpr(parser, index))
+            }
+            HirExpressionKind::SafeDotAccess { expr, name } => {
+                format!("{}?.{}", sexpr(parser, expr), slice(*name))
+            }
+            _ => panic!("unexpected expression {expr:?}"),
+        }
+    }
+
+    /// Parses `expr` as the initializer of a local and formats it with explicit parentheses.
+    fn parse(expr: &str) -> Result<String> {
+        let source = format!("fun main() {{ const x = {expr}; }}");
+        let mut parser = Parser::new(Rc::from(source.as_str()));
+        parser.parse()?;
+        let body = parser.ast.root.functions[0].body.as_ref().unwrap();
+        let HirStatementKind::ConstDecl {
+            expr: Some(expr), ..
+        } = &body.statements[0].kind
+        else {
+            panic!("expected a const declaration");
+        };
+        Ok(sexpr(&parser, expr))
+    }
+
+    /// Parses a source that must not contain errors.
+    fn parse_source(source: &str) -> Parser {
+        let mut parser = Parser::new(Rc::from(source));
+        if parser.parse().is_err() {
+            let messages = parser
+                .errors
+                .iter()
+                .map(|err| err.message(&parser.lex))
+                .collect::<Vec<_>>();
+            panic!("{source}: {messages:?}");
+        }
+        parser
+    }
+
+    /// Returns the body of the `index`th root function.
+    fn body(parser: &Parser, index: usize) -> &HirBlock {
+        parser.ast.root.functions[index].body.as_ref().unwrap()
+    }
+
+    /// Returns the value of the `index`th statement, a `const` declaration.
+    fn const_value(block: &HirBlock, index: usize) -> &HirExpression {
+        let HirStatementKind::ConstDecl {
+            expr: Some(expr), ..
+        } = &block.statements[index].kind
+        else {
+            panic!("expected a const declaration");
+        };
+        expr
+    }
+
+    fn operators() -> impl Iterator<Item = (usize, Assoc, &'static str)> {
+        LEVELS
+            .iter()
+            .enumerate()
+            .flat_map(|(level, (ops, assoc))| ops.iter().map(move |op| (level, *assoc, *op)))
+    }
+
+    #[test]
+    fn binary_operator_pairs() {
+        for (level1, assoc, op1) in operators() {
+            for (level2, _, op2) in operators() {
+                let source = format!("a {op1} b {op2} c");
+                let left = format!("((a {op1} b) {op2} c)");
+                let right = format!("(a {op1} (b {op2} c))");
+                let expected = if level1 > level2 {
+                    Some(left)
+                } else if level1 < level2 {
+                    Some(right)
+                } else {
+                    match assoc {
+                        Assoc::Left => Some(left),
+                        Assoc::Right => Some(right),
+                        Assoc::None => None,
+                    }
+                };
+                match (parse(&source), expected) {
+                    (Ok(actual), Some(expected)) => assert_eq!(actual, expected, "{source}"),
+                    (Err(Error::NonAssociativeOperator(_)), None) => {}
+                    (actual, expected) => panic!("{source}: {actual:?}, expected {expected:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unary_operators_bind_tighter_than_binary_operators() {
+        for unary in UNARY {
+            for (_, _, op) in operators() {
+                assert_eq!(
+                    parse(&format!("{unary}a {op} {unary}b")).unwrap(),
+                    format!("(({unary}a) {op} ({unary}b))"),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nested_unary_operators() {
+        assert_eq!(parse("-!*a").unwrap(), "(-(!(*a)))");
+        assert_eq!(parse("&const *a").unwrap(), "(&const (*a))");
+        assert_eq!(parse("- -a").unwrap(), "(-(-a))");
+    }
+
+    #[test]
+    fn casts_bind_tighter_than_binary_operators() {
+        for (_, _, op) in operators() {
+            assert_eq!(
+                parse(&format!("a as T {op} b as U")).unwrap(),
+                format!("((a as T) {op} (b as U))"),
+            );
+        }
+        assert_eq!(parse("-a as T").unwrap(), "((-a) as T)");
+        assert_eq!(parse("a as T as U").unwrap(), "((a as T) as U)");
+    }
+
+    #[test]
+    fn cast_targets_are_types() {
+        let parser =
+            parse_source("fun f() { const a = p as *const u8?; const b = x as geo.Point; }");
+        let slice = |slice| parser.lex.slice(slice);
+        let HirExpressionKind::Cast { expr, r#type } = &const_value(body(&parser, 0), 0).kind
+        else {
+            panic!("expected a cast");
+        };
+        assert!(matches!(expr.kind, HirExpressionKind::Access { .. }));
+        let HirTypeKind::Optional { r#type } = &r#type.kind else {
+            panic!("expected an optional type");
+        };
+        let HirTypeKind::ConstReference { r#type } = &r#type.kind else {
+            panic!("expected a const reference");
+        };
+        assert_eq!(slice(r#type.span), "u8");
+        let HirExpressionKind::Cast { r#type, .. } = &const_value(body(&parser, 0), 1).kind else {
+            panic!("expected a cast");
+        };
+        let HirTypeKind::Direct { path } = &r#type.kind else {
+            panic!("expected a type path");
+        };
+        let parts = path
+            .parts
+            .iter()
+            .map(|part| slice(*part))
+            .collect::<Vec<_>>();
+        assert_eq!(parts, ["geo", "Point"]);
+    }
+
+    #[test]
+    fn postfix_binds_tighter_than_unary_operators() {
+        assert_eq!(parse("-a.b").unwrap(), "(-a.b)");
+        assert_eq!(parse("*f(a + b)?").unwrap(), "(*f((a + b))?)");
+    }
+
+    #[test]
+    fn mixed_precedence_levels() {
+        assert_eq!(
+            parse("a + b * c == d or e").unwrap(),
+            "(((a + (b * c)) == d) or e)"
+        );
+        assert_eq!(
+            parse("a or b and c | d ^ e & f == g < h ?? i << j + k * l").unwrap(),
+            "(a or (b and (c | (d ^ (e & (f == (g < (h ?? (i << (j + (k * l)))))))))))"
+        );
+        assert_eq!(
+            parse("a * b + c << d ?? e < f == g & h ^ i | j and k or l").unwrap(),
+            "(((((((((((a * b) + c) << d) ?? e) < f) == g) & h) ^ i) | j) and k) or l)"
+        );
+        assert_eq!(parse("a - b - c").unwrap(), "((a - b) - c)");
+        assert_eq!(parse("a ?? b ?? c").unwrap(), "(a ?? (b ?? c))");
+        assert_eq!(parse("(a < b) == (c < d)").unwrap(), "((a < b) == (c < d))");
+    }
+
+    #[test]
+    fn comparison_chains_are_rejected() {
+        for source in ["a < b < c", "a == b != c", "a >= b <= c"] {
+            assert!(
+                matches!(parse(source), Err(Error::NonAssociativeOperator(_))),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn attributes() {
+        let parser = parse_source(
+            r#"
+@inline
+#[test, cfg(debug)]
+@deprecated("use g", since = 2)
+fun f() {}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let attributes = &parser.ast.root.functions[0].attributes;
+        let names = attributes
+            .iter()
+            .map(|attribute| slice(attribute.name))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["inline", "test", "cfg", "deprecated"]);
+        assert!(attributes[0].args.is_empty());
+        assert_eq!(attributes[2].args.len(), 1);
+        let args = &attributes[3].args;
+        assert_eq!(args.len(), 2);
+        assert!(args[0].name.is_none());
+        assert!(matches!(
+            args[0].value.kind,
+            HirExpressionKind::String { .. }
+        ));
+        assert_eq!(args[1].name.map(slice), Some("since"));
+        assert!(matches!(args[1].value.kind, HirExpressionKind::Int { .. }));
+    }
+
+    #[test]
+    fn module_tree() {
+        let parser = parse_source(
+            r#"
+module util;
+pub module geometry {
+    const origin = 0;
+    module shapes {
+        fun area() {}
+    }
+}
+fun main() {}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let modules = &parser.ast.root.modules;
+        assert_eq!(slice(modules[0].name), "util");
+        assert!(modules[0].body.is_none());
+        assert_eq!(modules[1].visibility, HirVisibility::Public);
+        let geometry = modules[1].body.as_ref().unwrap();
+        assert_eq!(geometry.constants.len(), 1);
+        assert!(geometry.functions.is_empty());
+        let shapes = geometry.modules[0].body.as_ref().unwrap();
+        assert_eq!(slice(shapes.functions[0].name), "area");
+        assert_eq!(parser.ast.root.functions.len(), 1);
+    }
+
+    #[test]
+    fn imports() {
+        let parser = parse_source(
+            r#"
+import std:{io.println, math.sqrt as root};
+import std.io.*;
+pub(package) import geometry.area;
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let imports = parser
+            .ast
+            .root
+            .imports
+            .iter()
+            .map(|import| {
+                let path = import.path.parts.iter().map(|part| slice(*part));
+                (
+                    path.collect::<Vec<_>>().join("."),
+                    import.alias.map(slice),
+                    import.glob,
+                    import.visibility,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            [
+                ("std.io.println".into(), None, false, HirVisibility::Private),
+                (
+                    "std.math.sqrt".into(),
+                    Some("root"),
+                    false,
+                    HirVisibility::Private
+                ),
+                ("std.io".into(), None, true, HirVisibility::Private),
+                ("geometry.area".into(), None, false, HirVisibility::Package),
+            ]
+        );
+    }
+
+    #[test]
+    fn type_aliases() {
+        let parser = parse_source("pub type Meters = float;\ntype Name = String?;");
+        let slice = |slice| parser.lex.slice(slice);
+        let aliases = parser
+            .ast
+            .root
+            .types
+            .iter()
+            .map(|decl| {
+                let HirTypeDecl::Alias {
+                    name,
+                    visibility,
+                    target,
+                    ..
+                } = decl
+                else {
+                    panic!("expected a type alias");
+                };
+                (slice(*name), *visibility, slice(target.span))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            aliases,
+            [
+                ("Meters", HirVisibility::Public, "float"),
+                ("Name", HirVisibility::Private, "String?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn supertraits_and_default_functions() {
+        let parser = parse_source(
+            r#"
+trait Measurable : Length, geo.Sized {
+    fun length(*const self) -> float;
+    fun double_length(*const self) -> float {
+        return self.length() * 2.0;
+    }
+}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let HirTypeDecl::Trait {
+            supertraits,
+            functions,
+            ..
+        } = &parser.ast.root.types[0]
+        else {
+            panic!("expected a trait");
+        };
+        let supertraits = supertraits
+            .iter()
+            .map(|supertrait| slice(supertrait.span))
+            .collect::<Vec<_>>();
+        assert_eq!(supertraits, ["Length", "geo.Sized"]);
+        assert!(functions[0].is_abstract());
+        assert!(!functions[1].is_abstract());
+        assert_eq!(functions[1].visibility, HirVisibility::Public);
+    }
+
+    #[test]
+    fn inherent_and_trait_impls() {
+        let parser = parse_source(
+            r#"
+impl Vec3 {
+    pub fun dot(*self, Vec3 other) -> float {}
+}
+impl Vec3 : Length {
+    fun len(*const self) -> float {}
+}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let impls = &parser.ast.root.impls;
+        assert_eq!(slice(impls[0].target.span), "Vec3");
+        assert!(impls[0].r#trait.is_none());
+        assert_eq!(impls[0].functions[0].visibility, HirVisibility::Public);
+        assert_eq!(
+            impls[1].r#trait.as_ref().map(|r#trait| slice(r#trait.span)),
+            Some("Length")
+        );
+        assert_eq!(slice(impls[1].functions[0].name), "len");
+    }
+
+    #[test]
+    fn loop_labels_and_break_values() {
+        let parser = parse_source(
+            r#"
+fun f() {
+    var outer = 1;
+    'outer: loop {
+        loop {
+            break outer;
+        }
+        continue 'outer;
+    }
+    const found = 'search: loop {
+        break 'search 42;
+    };
+}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let statements = &body(&parser, 0).statements;
+        let HirStatementKind::Loop {
+            label: Some(label),
+            block,
+        } = &statements[1].kind
+        else {
+            panic!("expected a labeled loop");
+        };
+        assert_eq!(slice(*label), "outer");
+        let HirStatementKind::Loop { block: inner, .. } = &block.statements[0].kind else {
+            panic!("expected an inner loop");
+        };
+        // `outer` without `'` is the variable
+        let HirStatementKind::Break {
+            label: None,
+            value: Some(value),
+        } = &inner.statements[0].kind
+        else {
+            panic!("expected a break with a value");
+        };
+        assert_eq!(slice(value.span), "outer");
+        let HirStatementKind::Continue { label: Some(label) } = &block.statements[1].kind else {
+            panic!("expected a labeled continue");
+        };
+        assert_eq!(slice(*label), "outer");
+        let HirExpressionKind::Loop {
+            label: Some(label),
+            block,
+        } = &const_value(body(&parser, 0), 2).kind
+        else {
+            panic!("expected a labeled loop expression");
+        };
+        assert_eq!(slice(*label), "search");
+        assert!(matches!(
+            &block.statements[0].kind,
+            HirStatementKind::Break {
+                label: Some(_),
+                value: Some(_)
+            }
+        ));
+    }
+
+    #[test]
+    fn unknown_labels_are_rejected() {
+        let parser =
+            parse_with_errors("fun f() {\n    'outer: loop {\n        break 'outr;\n    }\n}");
+        assert_eq!(error_slices(&parser), ["'outr"]);
+        assert!(matches!(parser.errors[0], Error::UnknownLabel(_)));
+    }
+
+    #[test]
+    fn match_arms_and_patterns() {
+        let parser = parse_source(
+            r#"
+fun f() {
+    const y = match x {
+        -1 => 0,
+        Shape.Circle(r, _) => { r }
+        Shape.Empty => 1,
+        n => n
+    };
+}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let HirExpressionKind::Match { expr, arms } = &const_value(body(&parser, 0), 0).kind else {
+            panic!("expected a match");
+        };
+        assert_eq!(sexpr(&parser, expr), "x");
+        assert_eq!(arms.len(), 4);
+        let HirPatternKind::Literal { value } = &arms[0].pattern.kind else {
+            panic!("expected a literal pattern");
+        };
+        assert_eq!(sexpr(&parser, value), "(-1)");
+        let HirPatternKind::Variant { path, fields } = &arms[1].pattern.kind else {
+            panic!("expected a variant pattern");
+        };
+        assert_eq!(slice(path.parts[1]), "Circle");
+        assert!(matches!(
+            fields.as_slice(),
+            [
+                HirPattern {
+                    kind: HirPatternKind::Binding { .. },
+                    ..
+                },
+                HirPattern {
+                    kind: HirPatternKind::Wildcard,
+                    ..
+                }
+            ]
+        ));
+        assert!(matches!(
+            &arms[2].pattern.kind,
+            HirPatternKind::Variant { fields, .. } if fields.is_empty()
+        ));
+        assert!(matches!(
+            &arms[3].pattern.kind,
+            HirPatternKind::Binding { name } if slice(*name) == "n"
+        ));
+        assert_eq!(slice(arms[3].span), "n => n");
+    }
+
+    #[test]
+    fn block_like_statements_end_at_their_brace() {
+        let parser = parse_source(
+            r#"
+fun f() {
+    if a { 1 } else { 2 } - 3;
+    { 1 } - 1;
+    match x { _ => 1 } - 1;
+}
+"#,
+        );
+        let statements = &body(&parser, 0).statements;
+        assert_eq!(statements.len(), 6);
+        assert!(matches!(statements[0].kind, HirStatementKind::If { .. }));
+        assert!(matches!(
+            &statements[2].kind,
+            HirStatementKind::Expr {
+                expr: HirExpression {
+                    kind: HirExpressionKind::Block { .. },
+                    ..
+                }
+            }
+        ));
+        assert!(matches!(
+            &statements[4].kind,
+            HirStatementKind::Expr {
+                expr: HirExpression {
+                    kind: HirExpressionKind::Match { .. },
+                    ..
+                }
+            }
+        ));
+        for index in [1, 3, 5] {
+            let HirStatementKind::Expr { expr } = &statements[index].kind else {
+                panic!("expected an expression statement");
+            };
+            assert!(matches!(expr.kind, HirExpressionKind::UnaryOp { .. }));
+        }
+    }
+
+    #[test]
+    fn expression_statements_and_bare_return() {
+        let parser = parse_source(
+            r#"
+fun f() {
+    a + 1;
+    a.b;
+    g(a);
+    a.b = 1;
+    return;
+}
+"#,
+        );
+        let statements = &body(&parser, 0).statements;
+        let HirStatementKind::Expr { expr } = &statements[0].kind else {
+            panic!("expected an expression statement");
+        };
+        assert_eq!(sexpr(&parser, expr), "(a + 1)");
+        let HirStatementKind::Expr { expr } = &statements[1].kind else {
+            panic!("expected an expression statement");
+        };
+        assert_eq!(sexpr(&parser, expr), "a.b");
+        assert!(matches!(statements[2].kind, HirStatementKind::Call { .. }));
+        assert!(matches!(
+            statements[3].kind,
+            HirStatementKind::Assign { .. }
+        ));
+        assert!(matches!(
+            statements[4].kind,
+            HirStatementKind::Return { expr: None }
+        ));
+    }
+
+    #[test]
+    fn unassignable_expressions_are_rejected() {
+        let parser = parse_with_errors("fun f() {\n    a + 1 = 2;\n}");
+        assert_eq!(error_slices(&parser), ["a + 1"]);
+        assert!(matches!(parser.errors[0], Error::UnexpectedExpression(_)));
+    }
+
+    #[test]
+    fn optional_types_and_null_safe_operators() {
+        assert_eq!(parse("a?.b?.c ?? d ?? e").unwrap(), "(a?.b?.c ?? (d ?? e))");
+        assert_eq!(parse("a ?? b + c < d").unwrap(), "((a ?? (b + c)) < d)");
+        assert_eq!(parse("a?.b()").unwrap(), "a?.b()");
+        assert_eq!(parse("a as T ?? b").unwrap(), "((a as T) ?? b)");
+        let parser = parse_source("fun f(*int? a, int?? b) {}");
+        let params = &parser.ast.root.functions[0].params;
+        let HirTypeKind::Optional { r#type } = &params[0].r#type.kind else {
+            panic!("expected an optional type");
+        };
+        assert!(matches!(r#type.kind, HirTypeKind::Reference { .. }));
+        let HirTypeKind::Optional { r#type } = &params[1].r#type.kind else {
+            panic!("expected an optional type");
+        };
+        let HirTypeKind::Optional { r#type } = &r#type.kind else {
+            panic!("expected a nested optional type");
+        };
+        assert_eq!(parser.lex.slice(r#type.span), "int");
+        assert_eq!(parser.lex.slice(params[0].r#type.span), "*int?");
+    }
+
+    #[test]
+    fn try_binds_like_member_access() {
+        assert_eq!(parse("a.b()?").unwrap(), "a.b()?");
+        assert_eq!(parse("a?.b").unwrap(), "a?.b");
+        assert_eq!(parse("-a? + b?").unwrap(), "((-a?) + b?)");
+        assert_eq!(parse("a? as T").unwrap(), "(a? as T)");
+        let parser = parse_source("fun f() { const a = b? .c(); }");
+        let HirExpressionKind::Call { expr, .. } = &const_value(body(&parser, 0), 0).kind else {
+            panic!("expected a call");
+        };
+        let HirExpressionKind::DotAccess { expr, .. } = &expr.kind else {
+            panic!("expected a member access");
+        };
+        assert!(matches!(expr.kind, HirExpressionKind::Try { .. }));
+    }
+
+    #[test]
+    fn scoped_visibility() {
+        let parser = parse_source(
+            r#"
+pub fun a() {}
+pub(module) fun b() {}
+pub(package) fun c() {}
+fun d() {}
+pub(package) struct S {
+    pub(module) int x;
+    pub(package) fun f() {}
+}
+pub(module) import util;
+"#,
+        );
+        let root = &parser.ast.root;
+        let functions = root
+            .functions
+            .iter()
+            .map(|function| function.visibility)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            functions,
+            [
+                HirVisibility::Public,
+                HirVisibility::Module,
+                HirVisibility::Package,
+                HirVisibility::Private,
+            ]
+        );
+        let HirTypeDecl::Struct {
+            visibility,
+            fields,
+            functions,
+            ..
+        } = &root.types[0]
+        else {
+            panic!("expected a struct");
+        };
+        assert_eq!(*visibility, HirVisibility::Package);
+        assert_eq!(fields[0].visibility, HirVisibility::Module);
+        assert_eq!(functions[0].visibility, HirVisibility::Package);
+        assert_eq!(root.imports[0].visibility, HirVisibility::Module);
+    }
+
+    #[test]
+    fn unknown_visibility_scopes_are_rejected() {
+        let parser = parse_with_errors("pub(crate) fun a() {}\nfun b() {}");
+        assert_eq!(error_slices(&parser), ["crate"]);
+        assert_eq!(
+            parser.errors[0].message(&parser.lex),
+            "expected one of `module` or `package`, found identifier `crate`"
+        );
+    }
+
+    #[test]
+    fn impls_and_extern_blocks_cannot_be_public() {
+        let parser = parse_with_errors(
+            "pub impl Foo {}\npub(module) extern \"C\" {\n    fun f();\n}\nfun g() {}",
+        );
+        assert_eq!(error_slices(&parser), ["impl", "{"]);
+        let messages = parser
+            .errors
+            .iter()
+            .map(|err| err.message(&parser.lex))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "impls and extern blocks cannot be marked as `pub`, only their members",
+                "impls and extern blocks cannot be marked as `pub`, only their members",
+            ]
+        );
+        assert!(matches!(parser.errors[0], Error::VisibilityNotAllowed(_)));
+        assert_eq!(parser.ast.root.functions.len(), 1);
+    }
+
+    #[test]
+    fn extern_functions_and_blocks() {
+        let parser = parse_source(
+            r#"
+extern fun puts(*const u8 s) -> int;
+extern "system" {
+    @link_name("strlen")
+    pub fun c_strlen(*const u8 s) -> uint;
+}
+module inner {
+    extern "C" fun exported() {}
+}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let root = &parser.ast.root;
+        let externs = root
+            .functions
+            .iter()
+            .map(|function| {
+                let r#extern = function.r#extern.as_ref().unwrap();
+                (
+                    slice(function.name),
+                    function.visibility,
+                    r#extern.abi.map(slice),
+                    r#extern.link_name.map(slice),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            externs,
+            [
+                ("puts", HirVisibility::Private, None, None),
+                (
+                    "c_strlen",
+                    HirVisibility::Public,
+                    Some("system"),
+                    Some("strlen")
+                ),
+            ]
+        );
+        assert!(root.functions[1].attributes.is_empty());
+        let inner = root.modules[0].body.as_ref().unwrap();
+        let r#extern = inner.functions[0].r#extern.as_ref().unwrap();
+        assert_eq!(r#extern.abi.map(slice), Some("C"));
+        assert!(inner.functions[0].body.is_some());
+    }
+
+    #[test]
+    fn extern_is_only_allowed_at_module_level() {
+        let parser = parse_with_errors("impl S {\n    extern fun f();\n}");
+        assert_eq!(error_slices(&parser), ["extern"]);
+    }
+
+    #[test]
+    fn jumps_out_of_defer_are_rejected() {
+        let parser = parse_with_errors(
+            r#"
+fun f() -> Result {
+    'outer: loop {
+        defer {
+            return;
+        }
+        defer break;
+        defer {
+            if a {
+                continue 'outer;
+            }
+        }
+        defer g()?;
+    }
+    defer {
+        for x in xs {
+            if x {
+                break;
+            }
+            continue;
+        }
+        'inner: loop {
+            break 'inner;
+        }
+    }
+    return g()?;
+}
+"#,
+        );
+        assert_eq!(error_slices(&parser), ["return", "break", "continue", "?"]);
+        assert!(parser
+            .errors
+            .iter()
+            .all(|err| matches!(err, Error::JumpOutOfDefer(_))));
+    }
+
+    #[test]
+    fn address_of_and_dereference() {
+        assert_eq!(parse("&a.b").unwrap(), "(&a.b)");
+        assert_eq!(parse("&const a[0]").unwrap(), "(&const a[0])");
+        assert_eq!(parse("**p + 1").unwrap(), "((*(*p)) + 1)");
+        let parser = parse_source("fun f() {\n    *p = 1;\n    **q = *p;\n}");
+        let statements = &body(&parser, 0).statements;
+        let targets = statements
+            .iter()
+            .map(|statement| {
+                let HirStatementKind::Assign { expr, .. } = &statement.kind else {
+                    panic!("expected an assignment");
+                };
+                sexpr(&parser, expr)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(targets, ["(*p)", "(*(*q))"]);
+        let parser = parse_with_errors("fun f() {\n    &x = 1;\n}");
+        assert_eq!(error_slices(&parser), ["&x"]);
+    }
+
+    #[test]
+    fn receivers() {
+        let parser = parse_source(
+            r#"
+impl S {
+    fun a(self) {}
+    fun b(*self, int x) {}
+    fun c(*const self,) {}
+    fun d(int x) {}
+    fun e(*Self selfish) {}
+}
+"#,
+        );
+        let functions = &parser.ast.root.impls[0].functions;
+        let receivers = functions
+            .iter()
+            .map(|function| {
+                let receiver = function.receiver.map(|receiver| receiver.kind);
+                (receiver, function.params.len())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            receivers,
+            [
+                (Some(HirReceiverKind::Value), 0),
+                (Some(HirReceiverKind::Reference), 1),
+                (Some(HirReceiverKind::ConstReference), 0),
+                (None, 1),
+                (None, 1),
+            ]
+        );
+        let spans = functions
+            .iter()
+            .filter_map(|function| function.receiver)
+            .map(|receiver| parser.lex.slice(receiver.span))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["self", "*self", "*const self"]);
+    }
+
+    #[test]
+    fn invalid_receivers_are_rejected() {
+        let parser =
+            parse_with_errors("fun a(Self self) {}\nfun b(int x, *self) {}\nfun c(int self) {}");
+        assert_eq!(error_slices(&parser), ["Self self", "*self", "int self"]);
+        assert!(matches!(parser.errors[0], Error::InvalidReceiver(_)));
+    }
+
+    #[test]
+    fn bare_associated_types_are_projections() {
+        let parser = parse_source(
+            r#"
+trait Iterator {
+    fun next(*self) -> Item?;
+    fun key(*self) -> Item.Key;
+    type Item;
+    fun other(*self) -> Other;
+}
+impl Range : Iterator {
+    type Item = int;
+    fun next(*self) -> Item? {}
+}
+fun next() -> Item {}
+"#,
+        );
+        let slice = |slice| parser.lex.slice(slice);
+        let root = &parser.ast.root;
+        let HirTypeDecl::Trait { functions, .. } = &root.types[0] else {
+            panic!("expected a trait");
+        };
+        let return_type = |function: &HirFunction| {
+            let r#type = function.return_type.as_ref().unwrap();
+            match &r#type.kind {
+                HirTypeKind::Optional { r#type } => (**r#type).clone(),
+                _ => r#type.clone(),
+            }
+        };
+        let item = return_type(&functions[0]);
+        let HirTypeKind::Projection { r#type, name } = &item.kind else {
+            panic!("expected a projection");
+        };
+        assert!(matches!(r#type.kind, HirTypeKind::SelfType));
+        assert_eq!(slice(*name), "Item");
+        assert_eq!(slice(item.span), "Item");
+        let key = return_type(&functions[1]);
+        let HirTypeKind::Projection { r#type, name } = &key.kind else {
+            panic!("expected a projection");
+        };
+        assert_eq!(slice(*name), "Key");
+        assert_eq!(slice(key.span), "Item.Key");
+        assert!(matches!(r#type.kind, HirTypeKind::Projection { .. }));
+        assert!(matches!(
+            return_type(&functions[2]).kind,
+            HirTypeKind::Direct { .. }
+        ));
+        assert!(matches!(
+            return_type(&root.impls[0].functions[0]).kind,
+            HirTypeKind::Projection { .. }
+        ));
+        assert!(matches!(
+            return_type(&root.functions[0]).kind,
+            HirTypeKind::Direct { .. }
+        ));
+    }
+
+    #[test]
+    fn impl_associated_types_need_a_target() {
+        let parser = parse_with_errors("impl Range : Iterator {\n    type Item;\n}");
+        assert_eq!(error_slices(&parser), ["Item"]);
+        assert!(matches!(parser.errors[0], Error::MissingTypeTarget(_)));
+    }
+
+    #[test]
+    fn break_values_and_jumps_need_a_matching_loop() {
+        let parser = parse_with_errors(
+            r#"
+fun f() {
+    while a {
+        break 1;
+    }
+    'outer: for x in xs {
+        loop {
+            break 'outer x;
+        }
+    }
+    break;
+    continue;
+    const v = loop {
+        while b {
+            break;
+        }
+        break 2;
+    };
+}
+"#,
+        );
+        assert_eq!(error_slices(&parser), ["1", "x", "break", "continue"]);
+        assert!(matches!(parser.errors[0], Error::InvalidBreakValue(_)));
+        assert!(matches!(parser.errors[1], Error::InvalidBreakValue(_)));
+        assert!(matches!(parser.errors[2], Error::JumpOutsideLoop(_)));
+        assert!(matches!(parser.errors[3], Error::JumpOutsideLoop(_)));
+    }
+
+    #[test]
+    fn block_like_statements_accept_a_semicolon() {
+        let parser = parse_source(
+            r#"
+fun f() {
+    if c {};
+    while c {};
+    for x in xs {};
+    loop {};
+    'a: loop {};
+}
+fun g() -> int {
+    if c { 1 } else { 2 };
+}
+fun h() -> int {
+    if c { 1 } else { 2 }
+}
+"#,
+        );
+        let kinds = &body(&parser, 0).statements;
+        assert_eq!(kinds.len(), 5);
+        assert!(matches!(kinds[0].kind, HirStatementKind::If { .. }));
+        assert!(matches!(kinds[1].kind, HirStatementKind::While { .. }));
+        assert!(matches!(kinds[2].kind, HirStatementKind::For { .. }));
+        let HirStatementKind::Expr { expr } = &kinds[4].kind else {
+            panic!("expected an expression statement");
+        };
+        assert!(matches!(
+            expr.kind,
+            HirExpressionKind::Loop { label: Some(_), .. }
+        ));
+        assert_eq!(parser.lex.slice(expr.span), "'a: loop {}");
+        assert!(body(&parser, 0).tail.is_none());
+        // a `;` discards the value of an `if`-`else`
+        assert!(body(&parser, 1).tail.is_none());
+        assert!(body(&parser, 2).tail.is_some());
+    }
+
+    #[test]
+    fn receivers_cannot_have_attributes() {
+        let parser = parse_with_errors(
+            "fun f(@cfg(test) self) {}\nfun g(@a @b *const self, int x) {}\nfun h(@a int x, self) {}",
+        );
+        assert_eq!(error_slices(&parser), ["@cfg(test)", "@a @b", "self"]);
+        assert!(matches!(parser.errors[0], Error::AttributeOnReceiver(_)));
+        assert!(matches!(parser.errors[1], Error::AttributeOnReceiver(_)));
+        assert!(matches!(parser.errors[2], Error::InvalidReceiver(_)));
+        assert_eq!(
+            parser.errors[0].message(&parser.lex),
+            "attributes are not allowed on `self`"
+        );
+    }
+
+    #[test]
+    fn associated_constants() {
+        let parser = parse_source(
+            r#"
+struct Vec3 {
+    float x;
+    pub const ZERO = 0.0;
+}
+enum Color {
+    Red;
+    const DEFAULT: int = 1;
+}
+trait Bounded {
+    const MIN: int;
+    const MAX: int = 100;
+}
+impl Vec3 : Bounded {
+    const MIN: int = 0;
+}
+"#,
+        );
+        let root = &parser.ast.root;
+        let slice = |slice| parser.lex.slice(slice);
+        let HirTypeDecl::Struct { constants, .. } = &root.types[0] else {
+            panic!("expected a struct");
+        };
+        assert_eq!(slice(constants[0].name), "ZERO");
+        assert_eq!(constants[0].visibility, HirVisibility::Public);
+        let HirTypeDecl::Enum { constants, .. } = &root.types[1] else {
+            panic!("expected an enum");
+        };
+        assert_eq!(slice(constants[0].name), "DEFAULT");
+        let HirTypeDecl::Trait { constants, .. } = &root.types[2] else {
+            panic!("expected a trait");
+        };
+        assert!(constants[0].is_abstract());
+        assert!(!constants[1].is_abstract());
+        assert_eq!(slice(root.impls[0].constants[0].name), "MIN");
+        assert!(root.constants.is_empty());
+    }
+
+    #[test]
+    fn constants_need_a_value_outside_of_traits() {
+        let parser = parse_with_errors(
+            r#"
+impl Vec3 : Bounded {
+    const MIN: int;
+}
+struct S {
+    const A: int;
+}
+enum E {
+    const B: int;
+}
+const c: int;
+"#,
+        );
+        assert_eq!(error_slices(&parser), ["MIN", "A", "B", "c"]);
+        assert!(parser
+            .errors
+            .iter()
+            .all(|err| matches!(err, Error::MissingInitializer(_))));
+    }
+
+    #[test]
+    fn grouped_import_spans() {
+        let parser = parse_source("import a:{b.c, d.e, f:{g, h.*}};");
+        let spans = parser
+            .ast
+            .root
+            .imports
+            .iter()
+            .map(|import| parser.lex.slice(import.path.span))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["b.c", "d.e", "g", "h"]);
+        assert_eq!(parser.ast.root.imports[1].path.parts.len(), 3);
+    }
+
+    fn parse_with_errors(source: &str) -> Parser {
+        let mut parser = Parser::new(Rc::from(source));
+        assert!(parser.parse().is_err());
+        parser
+    }
+
+    fn error_slices(parser: &Parser) -> Vec<&str> {
+        parser
+            .errors
+            .iter()
+            .map(|err| err.slice().map_or("<eof>", |slice| parser.lex.slice(slice)))
+            .collect()
+    }
+
+    #[test]
+    fn recovers_at_items() {
+        let parser = parse_with_errors(
+            r#"
+const a = ;
+struct S { float x; }
+} fun f() {}
+pub 42;
+fun g(int) {
+    return 1;
+}
+fun h() {}
+"#,
+        );
+        assert_eq!(error_slices(&parser), [";", "}", "42", ")"]);
+        assert!(matches!(
+            &parser.errors[0],
+            Error::UnexpectedToken { expected, .. } if expected == &[Expected::Expression]
+        ));
+        let root = &parser.ast.root;
+        assert!(root.constants.is_empty());
+        assert_eq!(root.types.len(), 1);
+        let names = root
+            .functions
+            .iter()
+            .map(|function| parser.lex.slice(function.name))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["f", "h"]);
+    }
+
+    #[test]
+    fn recovers_at_statements() {
+        let parser = parse_with_errors(
+            r#"
+fun f() {
+    const b = );
+    var c = 1;
+    c = c +;
+    if c {
+        return );
+    }
+    return c;
+}
+fun g() {}
+"#,
+        );
+        assert_eq!(error_slices(&parser), [")", ";", ")"]);
+        let f = &parser.ast.root.functions[0];
+        let statements = &f.body.as_ref().unwrap().statements;
+        assert!(matches!(
+            statements[0].kind,
+            HirStatementKind::VarDecl { .. }
+        ));
+        let HirStatementKind::If { block, .. } = &statements[1].kind else {
+            panic!("expected an if statement");
+        };
+        assert!(block.statements.is_empty());
+        assert!(matches!(
+            statements[2].kind,
+            HirStatementKind::Return { .. }
+        ));
+        assert_eq!(parser.ast.root.functions.len(), 2);
+    }
+
+    #[test]
+    fn keeps_partial_items_at_eof() {
+        let parser = parse_with_errors("module m {\n    fun f() {\n        const a = 1;");
+        assert_eq!(parser.errors.len(), 1);
+        assert!(matches!(
+            parser.errors[0],
+            Error::UnexpectedEof { at, .. } if at == parser.lex.end()
+        ));
+        assert_eq!(
+            parser.errors[0].message(&parser.lex),
+            "expected `}`, found end of file in function `f` of module `m`"
+        );
+        let module = parser.ast.root.modules[0].body.as_ref().unwrap();
+        let body = module.functions[0].body.as_ref().unwrap();
+        assert_eq!(body.statements.len(), 1);
+    }
+
+    #[test]
+    fn number_literals_at_eof() {
+        for source in ["const a = 1", "const a = 1.5", "fun f() {\n    g(.5"] {
+            let parser = parse_with_errors(source);
+            assert_eq!(parser.errors.len(), 1, "{source}");
+            assert!(matches!(parser.errors[0], Error::UnexpectedEof { .. }));
+        }
+    }
+
+    #[test]
+    fn skips_strings_with_invalid_escapes() {
+        let parser = parse_with_errors(
+            r#"
+fun f() {
+    log("\q \" still inside", "rest");
+    log("\x4");
+    run();
+}
+fun g() {}
+"#,
+        );
+        assert_eq!(error_slices(&parser), ["\\q", "\\x4"]);
+        assert!(parser
+            .errors
+            .iter()
+            .all(|err| matches!(err, Error::InvalidEscapeSequence(_))));
+        assert_eq!(parser.ast.root.functions.len(), 2);
+        let body = body(&parser, 0);
+        assert_eq!(body.statements.len(), 1);
+    }
+
+    #[test]
+    fn errors_describe_expected_tokens_and_context() {
+        let parser = parse_with_errors(
+            r#"
+const a 1;
+struct Vec3 {
+    float x
+}
+module m {
+    fun f(int a b) {}
+}
+fun g() {
+    loop x;
+}
+"#,
+        );
+        let messages = parser
+            .errors
+            .iter()
+            .map(|err| format!("{}: {}", err.code(), err.message(&parser.lex)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "E0002: expected one of `:`, `=` or `;`, found integer `1` in constant `a`",
+                "E0002: expected `;`, found `}` in struct field `x` of struct `Vec3`",
+                "E0002: expected one of `,` or `)`, found identifier `b` in function `f` of module `m`",
+                "E0002: expected `{`, found identifier `x` in function `g`",
+            ]
+        );
+    }
+
+    #[test]
+    fn expressions_stopping_midway() {
+        let parser = parse_with_errors(
+            r#"
+const a = b c;
+const d = 1 +;
+fun f() {
+    g(a b);
+    return );
+    h(x as int y);
+}
+"#,
+        );
+        let messages = parser
+            .errors
+            .iter()
+            .map(|err| err.message(&parser.lex))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "expected one of `(`, `.`, `?.`, `?`, `[`, `as`, operator or `;`, found identifier `c` \
+                 in constant `a`",
+                "expected expression, found `;` in constant `d`",
+                "expected one of `(`, `.`, `?.`, `?`, `[`, `as`, operator, `,` or `)`, found \
+                 identifier `b` in function `f`",
+                "expected one of `;` or expression, found `)` in function `f`",
+                "expected one of `.`, `?`, `as`, operator, `,` or `)`, found identifier `y` in \
+                 function `f`",
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_nodes() {
+        let source = r#"
+@inline
+fun f(int a, int b) -> int {
+    defer log(a);
+    return (a + b) * c.d(1);
+}
+import std.io:{fs.File};
+extern "C" {
+    fun g(geo.Point p);
+}
+"#;
+        let mut parser = Parser::new(Rc::from(source));
+        parser.parse().unwrap();
+        let slice = |slice| parser.lex.slice(slice);
+        let function = &parser.ast.root.functions[0];
+        assert!(slice(function.span).starts_with("@inline\nfun f("));
+        assert!(slice(function.span).ends_with("c.d(1);\n}"));
+        assert_eq!(slice(function.params[1].span), "int b");
+        let body = function.body.as_ref().unwrap();
+        assert_eq!(slice(body.statements[0].span), "defer log(a);");
+        let HirStatementKind::Defer { statement } = &body.statements[0].kind else {
+            panic!("expected a defer statement");
+        };
+        assert_eq!(slice(statement.span), "log(a);");
+        let HirStatementKind::Return { expr: Some(expr) } = &body.statements[1].kind else {
+            panic!("expected a return statement");
+        };
+        assert_eq!(slice(expr.span), "(a + b) * c.d(1)");
+        let HirExpressionKind::BinaryOp { args, .. } = &expr.kind else {
+            panic!("expected a binary operation");
+        };
+        assert_eq!(slice(args[0].span), "(a + b)");
+        assert_eq!(slice(args[1].span), "c.d(1)");
+        assert_eq!(slice(parser.ast.root.imports[0].path.span), "fs.File");
+        let g = &parser.ast.root.functions[1];
+        assert_eq!(slice(g.r#extern.as_ref().unwrap().span), "extern \"C\"");
+        let HirTypeKind::Direct { path } = &g.params[0].r#type.kind else {
+            panic!("expected a type path");
+        };
+        assert_eq!(slice(path.span), "geo.Point");
     }
 }
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{borrow::Cow, rc::Rc};
 
 use phf::{phf_map, Map};
 
@@ -7,11 +7,14 @@ use crate::prelude::*;
 pub const KEYWORDS: Map<&str, TokenType> = phf_map! {
     "module" => TokenType::KwModule,
     "import" => TokenType::KwImport,
+    "pub" => TokenType::KwPub,
     "trait" => TokenType::KwTrait,
+    "type" => TokenType::KwType,
     "struct" => TokenType::KwStruct,
     "enum" => TokenType::KwEnum,
     "impl" => TokenType::KwImpl,
     "fun" => TokenType::KwFun,
+    "extern" => TokenType::KwExtern,
     "Self" => TokenType::KwSelf,
     "const" => TokenType::KwConst,
     "var" => TokenType::KwVar,
@@ -20,15 +23,19 @@ pub const KEYWORDS: Map<&str, TokenType> = phf_map! {
     "elseif" => TokenType::KwElseif,
     "for" => TokenType::KwFor,
     "while" => TokenType::KwWhile,
+    "loop" => TokenType::KwLoop,
     "in" => TokenType::KwIn,
     "return" => TokenType::KwReturn,
     "continue" => TokenType::KwContinue,
     "break" => TokenType::KwBreak,
+    "defer" => TokenType::KwDefer,
     "and" => TokenType::KwAnd,
     "or" => TokenType::KwOr,
+    "as" => TokenType::KwAs,
+    "match" => TokenType::KwMatch,
 };
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Token {
     pub r#type: TokenType,
     pub slice: Str,
@@ -58,7 +65,10 @@ pub enum TokenType {
     DotDot,
     DotDotDot,
     Arrow,
+    FatArrow,
     Comma,
+    Question,
+    QuestionDot,
     // Operators
     Bang,
     And,
@@ -87,20 +97,25 @@ pub enum TokenType {
     GreaterEqual,
     LessLess,
     GreaterGreater,
+    QuestionQuestion,
     // Literals
     Integer,
     Float,
     String,
     Identifier,
+    /// `'name`, the label of a loop
+    Label,
     // Keywords
     KwModule,
     KwImport,
     KwPub,
     KwTrait,
+    KwType,
     KwStruct,
     KwEnum,
     KwImpl,
     KwFun,
+    KwExtern,
     KwSelf,
     KwConst,
     KwVar,
@@ -109,12 +124,16 @@ pub enum TokenType {
     KwElseif,
     KwFor,
     KwWhile,
+    KwLoop,
     KwIn,
     KwReturn,
     KwContinue,
     KwBreak,
+    KwDefer,
     KwAnd,
     KwOr,
+    KwAs,
+    KwMatch,
 }
 
 impl TokenType {
@@ -140,11 +159,27 @@ impl TokenType {
          */
     }
 
+    /// Whether the token starts a statement that is not an expression.
+    pub fn is_statement_keyword(&self) -> bool {
+        matches!(
+            self,
+            Self::KwVar
+                | Self::KwConst
+                | Self::KwIf
+                | Self::KwWhile
+                | Self::KwFor
+                | Self::KwLoop
+                | Self::KwReturn
+                | Self::KwContinue
+                | Self::KwBreak
+                | Self::KwDefer
+        )
+    }
+
     pub fn is_binary_op(&self) -> bool {
         matches!(
             self,
-            Self::Bang
-                | Self::And
+            Self::And
                 | Self::Pipe
                 | Self::Caret
                 | Self::Plus
@@ -160,11 +195,75 @@ impl TokenType {
                 | Self::GreaterEqual
                 | Self::LessLess
                 | Self::GreaterGreater
+                | Self::QuestionQuestion
                 | Self::KwAnd
                 | Self::KwOr
         )
     }
 
+    /// Names the token for error messages, e.g. "`;`" or "identifier".
+    pub fn describe(&self) -> String {
+        let text = match self {
+            Self::LeftParen => "(",
+            Self::RightParen => ")",
+            Self::LeftBracket => "[",
+            Self::RightBracket => "]",
+            Self::LeftBrace => "{",
+            Self::RightBrace => "}",
+            Self::Colon => ":",
+            Self::Semicolon => ";",
+            Self::At => "@",
+            Self::Hashtag => "#",
+            Self::Dot => ".",
+            Self::DotDot => "..",
+            Self::DotDotDot => "...",
+            Self::Arrow => "->",
+            Self::FatArrow => "=>",
+            Self::Comma => ",",
+            Self::Question => "?",
+            Self::QuestionDot => "?.",
+            Self::Bang => "!",
+            Self::And => "&",
+            Self::Pipe => "|",
+            Self::Caret => "^",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Star => "*",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::Equal => "=",
+            Self::Less => "<",
+            Self::Greater => ">",
+            Self::BangEqual => "!=",
+            Self::AndEqual => "&=",
+            Self::PipeEqual => "|=",
+            Self::CaretEqual => "^=",
+            Self::PlusEqual => "+=",
+            Self::MinusEqual => "-=",
+            Self::StarEqual => "*=",
+            Self::SlashEqual => "/=",
+            Self::PercentEqual => "%=",
+            Self::EqualEqual => "==",
+            Self::LessEqual => "<=",
+            Self::GreaterEqual => ">=",
+            Self::LessLess => "<<",
+            Self::GreaterGreater => ">>",
+            Self::QuestionQuestion => "??",
+            Self::Integer => return "integer".to_string(),
+            Self::Float => return "float".to_string(),
+            Self::String => return "string".to_string(),
+            Self::Identifier => return "identifier".to_string(),
+            Self::Label => return "label".to_string(),
+            keyword => KEYWORDS
+                .entries()
+                .find(|(_, token_type)| *token_type == keyword)
+                .map(|(text, _)| *text)
+                .unwrap(),
+        };
+        format!("`{text}`")
+    }
+
+    /// Binding power of a binary operator, operators with a higher precedence bind tighter.
     pub fn precedence(&self) -> usize {
         match self {
             Self::KwOr => 1,
@@ -174,14 +273,40 @@ impl TokenType {
             Self::And => 5,
             Self::EqualEqual | Self::BangEqual => 6,
             Self::Less | Self::Greater | Self::LessEqual | Self::GreaterEqual => 7,
-            Self::LessLess | Self::GreaterGreater => 8,
-            Self::Plus | Self::Minus => 9,
-            Self::Star | Self::Slash | Self::Percent => 10,
+            Self::QuestionQuestion => 8,
+            Self::LessLess | Self::GreaterGreater => 9,
+            Self::Plus | Self::Minus => 10,
+            Self::Star | Self::Slash | Self::Percent => 11,
             _ => panic!("Invalid operator"),
         }
     }
+
+    /// How a binary operator groups with operators of the same precedence.
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            Self::EqualEqual
+            | Self::BangEqual
+            | Self::Less
+            | Self::Greater
+            | Self::LessEqual
+            | Self::GreaterEqual => Associativity::None,
+            Self::QuestionQuestion => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ?? b ?? c` is `a ?? (b ?? c)`
+    Right,
+    /// `a < b < c` is an error
+    None,
+}
+
+#[derive(Clone)]
 pub struct Lexer {
     source: Rc<str>,
     index: u32,
@@ -192,6 +317,20 @@ impl Lexer {
         Self { source, index: 0 }
     }
 
+    /// The empty slice at the end of the source
+    pub fn end(&self) -> Str {
+        Str(self.source.len() as u32, 0)
+    }
+
+    /// The error for a source that ends too early.
+    pub fn unexpected_eof(&self) -> Error {
+        Error::UnexpectedEof {
+            at: self.end(),
+            expected: Vec::with_capacity(0),
+            context: Vec::with_capacity(0),
+        }
+    }
+
     pub fn has_next(&self) -> bool {
         self.index < self.source.len() as u32
     }
@@ -199,10 +338,10 @@ impl Lexer {
     pub fn peek(&self) -> Result<char> {
         self.source
             .get(self.index as usize..)
-            .ok_or(Error::UnexpectedEof)?
+            .ok_or_else(|| self.unexpected_eof())?
             .chars()
             .next()
-            .ok_or(Error::UnexpectedEof)
+            .ok_or_else(|| self.unexpected_eof())
     }
 
     #[allow(clippy::should_implement_trait)]
@@ -237,6 +376,43 @@ impl Lexer {
         &self.source[slice.0 as usize..(slice.0 + slice.1) as usize]
     }
 
+    /// The contents of a string literal between its quotes.
+    pub fn string_contents(literal: Str) -> Str {
+        Str(literal.0 + 1, literal.1 - 2)
+    }
+
+    /// Resolves the escape sequences in the `contents` of a string literal, which were already
+    /// validated by [`Lexer::parse_string`].
+    pub fn unescape(&self, contents: Str) -> Cow<'_, str> {
+        let raw = self.slice(contents);
+        if !raw.contains('\\') {
+            return Cow::Borrowed(raw);
+        }
+        let mut value = String::with_capacity(raw.len());
+        let mut chars = raw.chars();
+        while let Some(ac) = chars.next() {
+            if ac != '\\' {
+                value.push(ac);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('x') => {
+                    let digits = chars.by_ref().take(2).collect::<String>();
+                    value.push(char::from(
+                        u8::from_str_radix(&digits, 16).unwrap_or_default(),
+                    ));
+                }
+                // `\"` and `\\`
+                Some(bc) => value.push(bc),
+                None => {}
+            }
+        }
+        Cow::Owned(value)
+    }
+
     pub fn next_token(&mut self) -> Result<Option<Token>> {
         loop {
             self.skip_whitespace();
@@ -256,6 +432,17 @@ impl Lexer {
                 '@' => TokenType::At,
                 '#' => TokenType::Hashtag,
                 ',' => TokenType::Comma,
+                '?' => match self.peek() {
+                    Ok('.') => {
+                        self.eat();
+                        TokenType::QuestionDot
+                    }
+                    Ok('?') => {
+                        self.eat();
+                        TokenType::QuestionQuestion
+                    }
+                    _ => TokenType::Question,
+                },
                 '!' => {
                     if self.maybe('=') {
                         TokenType::BangEqual
@@ -341,6 +528,8 @@ impl Lexer {
                 '=' => {
                     if self.maybe('=') {
                         TokenType::EqualEqual
+                    } else if self.maybe('>') {
+                        TokenType::FatArrow
                     } else {
                         TokenType::Equal
                     }
@@ -359,7 +548,7 @@ impl Lexer {
                 '>' => match self.peek() {
                     Ok('=') => {
                         self.eat();
-                        TokenType::LessEqual
+                        TokenType::GreaterEqual
                     }
                     Ok('>') => {
                         self.eat();
@@ -370,7 +559,8 @@ impl Lexer {
                 '.' | '0'..='9' => self.parse_number(index, c)?,
                 '"' => self.parse_string()?,
                 'a'..='z' | 'A'..='Z' | '_' => self.parse_identifier(index)?,
-                _ => return Err(Error::InvalidToken(Str(index, self.index))),
+                '\'' => self.parse_label(index)?,
+                _ => return Err(Error::InvalidToken(Str(index, self.index - index))),
             };
             return Ok(Some(Token::new(token_type, Str(index, self.index - index))));
         }
@@ -379,14 +569,14 @@ impl Lexer {
     pub fn parse_multiline_comment(&mut self) -> Result<()> {
         loop {
             let Ok(c) = self.peek() else {
-                return Err(Error::UnexpectedEof);
+                return Err(self.unexpected_eof());
             };
             self.eat();
             if c != '*' {
                 continue;
             }
             let Ok(c) = self.peek() else {
-                return Err(Error::UnexpectedEof);
+                return Err(self.unexpected_eof());
             };
             self.eat();
             if c == '/' {
@@ -398,22 +588,19 @@ impl Lexer {
 
     pub fn parse_number(&mut self, index: u32, c: char) -> Result<TokenType> {
         let mut is_float = c == '.';
-        let ac = self.peek();
-        if is_float && !matches!(ac, Ok('0'..='9')) {
-            return Ok(TokenType::Dot);
-        }
-        let ac = ac.unwrap();
-        if is_float && ac == '.' {
-            self.eat();
+        if is_float && self.maybe('.') {
             if self.maybe('.') {
                 return Ok(TokenType::DotDotDot);
             }
             return Ok(TokenType::DotDot);
         }
+        if is_float && !matches!(self.peek(), Ok('0'..='9')) {
+            return Ok(TokenType::Dot);
+        }
         while let Ok(bc) = self.peek() {
             if self.maybe('.') {
                 if is_float {
-                    return Err(Error::InvalidFloat(Str(index, self.index)));
+                    return Err(Error::InvalidFloat(Str(index, self.index - index)));
                 }
                 is_float = true;
                 continue;
@@ -430,47 +617,78 @@ impl Lexer {
         })
     }
 
+    /// Parses a string literal after its opening quote.
+    ///
+    /// After an invalid escape sequence the rest of the literal is skipped, so that lexing resumes
+    /// after it.
     pub fn parse_string(&mut self) -> Result<TokenType> {
         loop {
-            let ac = self.peek()?;
-            if ac == '\\' {
-                let escape_sequence_start = self.index;
-                self.eat();
-                let bc = self.peek()?;
-                match bc {
-                    '"' | '\\' | 'n' | 't' | 'r' => self.eat(),
-                    'x' => {
-                        self.eat();
-                        let cc = self.next()?;
-                        if !matches!(cc, '0'..='9' | 'a'..='f' | 'A'..='F') {
-                            return Err(Error::InvalidEscapeSequence(Str(
-                                escape_sequence_start,
-                                self.index,
-                            )));
-                        }
-                        let dc = self.next()?;
-                        if !matches!(dc, '0'..='9' | 'a'..='f' | 'A'..='F') {
-                            return Err(Error::InvalidEscapeSequence(Str(
-                                escape_sequence_start,
-                                self.index,
-                            )));
-                        }
+            match self.next()? {
+                '"' => break,
+                '\\' => {
+                    if let Err(err @ Error::InvalidEscapeSequence(_)) = self.parse_escape() {
+                        self.skip_string();
+                        return Err(err);
                     }
-                    _ => {
-                        return Err(Error::InvalidEscapeSequence(Str(
-                            escape_sequence_start,
-                            self.index,
-                        )))
+                }
+                _ => {}
+            }
+        }
+        Ok(TokenType::String)
+    }
+
+    /// Parses an escape sequence after its `\`.
+    fn parse_escape(&mut self) -> Result<()> {
+        let start = self.index - 1;
+        let invalid = |lex: &Self| Error::InvalidEscapeSequence(Str(start, lex.index - start));
+        match self.peek()? {
+            '"' | '\\' | 'n' | 't' | 'r' => self.eat(),
+            'x' => {
+                self.eat();
+                for _ in 0..2 {
+                    if !self.peek()?.is_ascii_hexdigit() {
+                        return Err(invalid(self));
                     }
+                    self.eat();
                 }
-                continue;
+            }
+            '\n' => return Err(invalid(self)),
+            _ => {
+                self.eat();
+                return Err(invalid(self));
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the rest of a string literal up to its closing quote or the end of the line.
+    fn skip_string(&mut self) {
+        while let Ok(ac) = self.peek() {
+            if ac == '\n' {
+                break;
             }
             self.eat();
-            if ac == '"' {
+            match ac {
+                '"' => break,
+                // skips an escaped quote
+                '\\' if self.peek().is_ok_and(|bc| bc != '\n') => self.eat(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Parses the name of a label after its `'`.
+    pub fn parse_label(&mut self, index: u32) -> Result<TokenType> {
+        if !matches!(self.peek(), Ok('a'..='z' | 'A'..='Z' | '_')) {
+            return Err(Error::InvalidToken(Str(index, self.index - index)));
+        }
+        while let Ok(ac) = self.peek() {
+            if !ac.is_ascii_alphanumeric() && ac != '_' {
                 break;
             }
+            self.eat();
         }
-        Ok(TokenType::String)
+        Ok(TokenType::Label)
     }
 
     pub fn parse_identifier(&mut self, index: u32) -> Result<TokenType> {
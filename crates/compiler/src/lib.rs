@@ -19,23 +19,66 @@ mod test {
 // A module from another file
 module util;
 
+// An inline module
+module geometry {
+    pub const origin = 0;
+    pub var scale_factor: float = 1.0;
+    pub type Point = Vec3;
+
+    module shapes {
+        pub(package) fun area(float w, float h) -> float {
+            return scale(w) * h;
+        }
+
+        pub(module) fun scale(float x) -> float {
+            return x;
+        }
+    }
+}
+
 import std:{io.println, math.sqrt, Array};
+import std.math.sqrt as root;
+import std.io.*;
+pub import geometry.shapes.area;
 
 trait Length {
-    fun length(Self self) -> float;
+    const DIMENSIONS: int;
+    const UNIT = "m";
+
+    fun length(*const self) -> float;
+}
+
+trait Measurable : Length, Sized {
+    fun double_length(*const self) -> float {
+        return self.length() * 2.0;
+    }
+}
+
+trait Iterator {
+    type Item;
+    type Error : Display = String;
+
+    fun next(*self) -> Self.Item?;
 }
 
 enum Number {
+    @default
     integer(int);
     float(float);
     none;
+
+    const DEFAULT = 0;
 }
 
+#[packed]
 struct Vec3 {
+    @doc("horizontal")
     float x;
     float y;
     float z;
 
+    pub const ZERO: Vec3 = Vec3.new(0.0, 0.0, 0.0);
+
     fun new(float x, float y, float z) -> Vec3 {
         // Maybe add `x: x` -> `x` from Rust
         // return Vec3{
@@ -45,7 +88,8 @@ struct Vec3 {
         // };
     }
 
-    fun add(Self self, Vec3 other) -> Vec3 {
+    @inline
+    fun add(self, Vec3 other) -> Vec3 {
         return Vec3.new(
             self.x + other.x,
             self.y + other.y,
@@ -55,21 +99,43 @@ struct Vec3 {
 }
 
 impl Vec3 : Length {
-    // todo: self type and param
-    fun len(Self self) -> float {
+    const DIMENSIONS = 3;
+
+    @deprecated("use length")
+    fun len(*const self) -> float {
         return sqrt(self.x * self.x + self.y * self.y + self.z * self.z);
     }
 }
 
+impl Vec3 : Iterator {
+    type Item = float;
+
+    fun next(*self) -> float? {
+        return self.x;
+    }
+}
+
+impl Vec3 {
+    pub fun dot(*self, Vec3 other) -> float {
+        return self.x * other.x + self.y * other.y + self.z * other.z;
+    }
+}
+
 const a = 1;
+#[cfg(feature = "counters")]
+var counter = 0;
+var unit = geometry.scale_factor * 2.0;
+
+type Meters = float;
 
 fun do_math(int x, int y) -> int {
     const z = 7;
+    const half = x as float * 0.5;
     return x + y * 7;
 }
 
 // todo: -> Array<int>
-fun create_array(int start, int end) -> Array {
+fun create_array(int start, @unused int end) -> Array {
     var array = Array.new(end - start + 1);
     // for i in 0..(end - start) {
     //     array[i] = i + start;
@@ -77,20 +143,134 @@ fun create_array(int start, int end) -> Array {
     return array;
 }
 
+fun find(Array grid, int target) -> int {
+    var found = 0;
+    'outer: for row in grid {
+        for cell in row {
+            if cell == target {
+                found = cell;
+                break 'outer;
+            }
+            if cell < 0 {
+                continue 'outer;
+            }
+        }
+    }
+    const first = loop {
+        break found;
+    };
+    'search: loop {
+        while found > 0 {
+            found = found - 1;
+            continue 'search;
+        }
+        break;
+    }
+    const sign = match found {
+        0 => 0,
+        n => { n }
+    };
+    return first;
+}
+
+fun sign(int x) -> int {
+    const sign = if x < 0 { -1 } else { 1 };
+    const magnitude = {
+        const doubled = x * 2;
+        doubled / 2
+    };
+    if magnitude == 0 {
+        0
+    } elseif sign < 0 {
+        -1
+    } else {
+        1
+    }
+}
+
+fun first_name(Array names) -> String? {
+    const first: String? = names.first();
+    const length = first?.len() ?? 0;
+    return first ?? "anonymous";
+}
+
+enum ParseResult {
+    ok(int);
+    err(String);
+}
+
+fun parse_int(String s) -> ParseResult;
+
+fun parse_sum(String a, String b) -> ParseResult {
+    const sum = parse_int(a)? + parse_int(b)?;
+    return ParseResult.ok(sum);
+}
+
+extern "C" fun puts(*const u8 s) -> int;
+
+extern "C" {
+    @link_name("strlen")
+    fun c_strlen(*const u8 s) -> uint;
+    pub fun abs(int x) -> int;
+}
+
+extern "C" fun callback(int x) -> int {
+    return x;
+}
+
+fun write_log(String path, String message) {
+    const file = File.open(path);
+    defer file.close();
+    const lock = file.lock();
+    defer {
+        lock.release();
+        file.flush();
+    }
+    if message == "" {
+        return;
+    }
+    file.write(message);
+}
+
+fun swap(*int a, *int b) {
+    const tmp = *a;
+    *a = *b;
+    *b = tmp;
+}
+
 fun main() {
     println("Hello world!");
     const my_vec = Vec3.new(5.0, 2.0, 3.0);
+    var x = 1;
+    var y = 2;
+    @cfg(debug)
+    println("swapping");
+    swap(&x, &y);
+    const view = &const my_vec;
+    {
+        const scoped = my_vec.x;
+        scoped * 2.0;
+    }
+    if my_vec.x > 1.0 {
+        return;
+    }
     // const my_num = Number{ int: 10 };
 }
         "#,
         ));
-        if let Err(err) = parser.parse() {
-            if let Some(slice) = err.slice() {
-                let rest = parser.lex.slice(Str(0, slice.0));
-                let slice = parser.lex.slice(slice);
-                eprintln!("Error: {err:?}\n----------------\n{rest}**{slice}**<----");
-            } else {
-                eprintln!("Error: {err:?}");
+        if parser.parse().is_err() {
+            for err in &parser.errors {
+                let message = err.message(&parser.lex);
+                if let Some(slice) = err.slice() {
+                    let rest = parser.lex.slice(Str(0, slice.0));
+                    let slice = parser.lex.slice(slice);
+                    eprintln!(
+                        "error[{}]: {message}\n----------------\n{rest}**{slice}**<----",
+                        err.code()
+                    );
+                } else {
+                    eprintln!("error[{}]: {message}", err.code());
+                }
             }
             exit(1);
         }